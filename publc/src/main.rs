@@ -1,35 +1,166 @@
 use clap::Clap;
-use publication::{extensions, Emitter, Parser};
+use publication::{
+    extensions, BlockReader, CommentSyntax, Dialect, Emitter, HtmlEmitter, MarkdownImporter, ReadError, Severity,
+    StreamingEmitter,
+};
 use std::convert::TryInto;
-use std::fs::{read_to_string, write};
+use std::fs::{read_to_string, remove_file, rename, write, File};
+use std::io::{stdin, stdout, BufWriter, Read, Write};
 use std::path::PathBuf;
+use std::process::exit;
 use std::time::Instant;
 
 #[derive(Clap, Debug)]
 struct Options {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    input: Option<PathBuf>,
+    #[clap(short, long)]
+    out: Option<PathBuf>,
+    #[clap(long)]
+    deny_warnings: bool,
+    #[clap(flatten)]
+    extensions: Extensions,
+    #[clap(flatten)]
+    comments: Comments,
+}
+
+#[derive(Clap, Debug)]
+enum Command {
+    /// Converts a Markdown document into Publication source
+    Import(Import),
+    /// Rewrites .publ files in a canonical format
+    Fmt(Fmt),
+}
+
+#[derive(Clap, Debug)]
+struct Fmt {
+    #[clap(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Only checks that the files are formatted, failing if any are not
+    #[clap(long)]
+    check: bool,
+    #[clap(short, long, default_value = "80")]
+    width: usize,
+    #[clap(flatten)]
+    extensions: Extensions,
+    #[clap(flatten)]
+    comments: Comments,
+}
+
+#[derive(Clap, Debug)]
+struct Import {
     input: PathBuf,
     #[clap(short, long)]
     out: Option<PathBuf>,
+    #[clap(flatten)]
+    extensions: Extensions,
+    #[clap(flatten)]
+    comments: Comments,
+}
 
-    // Built-in extensions
+// Built-in extensions
+#[derive(Clap, Debug, Clone)]
+struct Extensions {
     #[clap(short, long)]
     bold: bool,
     #[clap(short, long)]
     italics: bool,
     #[clap(short, long)]
     list: Option<String>,
+    #[clap(short = "n", long)]
+    ordered_lists: bool,
+}
+
+// Comment syntax
+#[derive(Clap, Debug, Clone)]
+struct Comments {
+    /// Starts comments that run to the end of the line, instead of `#`
+    #[clap(long)]
+    line_comments: Option<String>,
+    /// Opens and closes block comments, like `--block-comments '#[' ']#'`
+    #[clap(long, number_of_values = 2)]
+    block_comments: Vec<String>,
+    /// Treats all text as content, including `#`
+    #[clap(long)]
+    no_comments: bool,
+}
+
+impl Comments {
+    fn syntax(self) -> CommentSyntax {
+        if self.no_comments {
+            return CommentSyntax::none();
+        }
+        let syntax = match self.line_comments {
+            Some(marker) => CommentSyntax::line(marker),
+            None => CommentSyntax::default(),
+        };
+        match self.block_comments.as_slice() {
+            [open, close] => syntax.with_block(open.as_str(), close.as_str()),
+            _ => syntax,
+        }
+    }
 }
 
 fn main() {
     let Options {
+        command,
         input,
         out,
+        deny_warnings,
+        extensions,
+        comments,
+    } = Options::parse();
+
+    match (command, input) {
+        (Some(Command::Import(import)), _) => markdown_import(import),
+        (Some(Command::Fmt(fmt)), _) => format(fmt),
+        (None, Some(input)) => compile(input, out, deny_warnings, extensions, comments),
+        (None, None) => eprintln!("Please provide a .publ file to compile."),
+    }
+}
+
+fn dialect(extensions: Extensions, comments: Comments) -> Dialect {
+    let Extensions {
         bold,
         italics,
         list,
-    } = Options::parse();
+        ordered_lists,
+    } = extensions;
+
+    let mut dialect = Dialect::new();
+    dialect.comment_syntax(comments.syntax());
+
+    if bold {
+        dialect.add_extension(extensions::Bold);
+    }
 
+    if italics {
+        dialect.add_extension(extensions::Italics);
+    }
+
+    if let Some(bullet) = list {
+        dialect.add_extension(extensions::Lists::new(bullet));
+    }
+
+    if ordered_lists {
+        dialect.add_extension(extensions::OrderedLists);
+    }
+
+    dialect
+}
+
+fn compile(
+    input: PathBuf,
+    out: Option<PathBuf>,
+    deny_warnings: bool,
+    extensions: Extensions,
+    comments: Comments,
+) {
+    // A `-` reads the source from stdin, or writes the output to stdout.
+    let from_stdin = input.as_os_str() == "-";
     let output = match input.extension() {
+        _ if from_stdin => out.unwrap_or_else(|| PathBuf::from("-")),
         Some(ext) if ext == "publ" => out.unwrap_or_else(|| {
             let mut output = input.clone();
             output.set_extension("html");
@@ -40,26 +171,32 @@ fn main() {
                 "Publication files must use the .publ extension, so .{} cannot be used.",
                 i.to_string_lossy()
             );
-            return;
+            exit(1);
         }
         None => {
             eprintln!("Publication files must use the .publ extension.");
-            return;
+            exit(1);
         }
     };
+    let to_stdout = output.as_os_str() == "-";
 
     let start = Instant::now();
 
-    let raw = match read_to_string(&input) {
-        Err(e) => {
-            eprintln!("Could not read {}: {}", input.display(), e);
-            return;
+    let reader: Box<dyn Read> = if from_stdin {
+        Box::new(stdin())
+    } else {
+        match File::open(&input) {
+            Err(e) => {
+                eprintln!("Could not read {}: {}", input.display(), e);
+                exit(1);
+            }
+            Ok(file) => Box::new(file),
         }
-        Ok(raw) => raw,
     };
 
     let emitter: Box<dyn Emitter> = match output.as_path().try_into() {
         Ok(e) => e,
+        Err(()) if to_stdout => Box::new(HtmlEmitter::new()),
         Err(()) => {
             match output.extension() {
                 None => {
@@ -71,44 +208,238 @@ fn main() {
                     eprintln!("No known emitter for .{} files.", ext.to_string_lossy());
                 }
             }
-            return;
+            exit(1);
         }
     };
 
-    let mut parser = Parser::new(raw);
+    let mut dialect = dialect(extensions, comments);
 
-    if bold {
-        parser.add_extension(extensions::Bold);
+    if deny_warnings {
+        dialect.deny_warnings();
     }
 
-    if italics {
-        parser.add_extension(extensions::Italics);
+    // The output is written next to where it goes and only moved there once
+    // the whole source compiled, so a failed compile keeps the last output.
+    let temporary = {
+        let mut name = std::ffi::OsString::from(".");
+        name.push(output.file_name().unwrap_or_default());
+        name.push(".tmp");
+        output.with_file_name(name)
+    };
+    let writer: Box<dyn Write> = if to_stdout {
+        Box::new(stdout())
+    } else {
+        match File::create(&temporary) {
+            Err(e) => {
+                eprintln!("Could not write to {}: {}", output.display(), e);
+                exit(1);
+            }
+            Ok(file) => Box::new(file),
+        }
+    };
+    let fail = || {
+        if !to_stdout {
+            let _ = remove_file(&temporary);
+        }
+        exit(1)
+    };
+
+    // Blocks are written out as soon as they are parsed, so that neither the
+    // whole source nor the whole output ever has to be kept in memory.
+    let mut blocks = dialect.read_blocks(reader).recovering();
+    if let Err(e) = write_blocks(&mut blocks, emitter.as_ref(), BufWriter::new(writer)) {
+        eprintln!("Could not compile {}: {}", input.display(), e);
+        fail();
     }
 
-    if let Some(bullet) = list {
-        parser.add_extension(extensions::Lists::new(bullet));
+    // The source is only read again to show where the diagnostics are, which
+    // can't be done for stdin.
+    let diagnostics = blocks.diagnostics();
+    let raw = match diagnostics.is_empty() || from_stdin {
+        true => None,
+        false => read_to_string(&input).ok(),
+    };
+    for diagnostic in diagnostics.iter() {
+        match &raw {
+            Some(raw) => eprintln!("{}", diagnostic.render(&input.display().to_string(), raw)),
+            None => eprintln!("{}", diagnostic),
+        }
+    }
+
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        eprintln!("Failed to parse {}.", input.display());
+        fail();
     }
 
-    let emitted = match parser.emit_with(emitter.as_ref()) {
-        Ok(s) => s,
+    if !to_stdout {
+        if let Err(e) = rename(&temporary, &output) {
+            eprintln!("Could not write to {}: {}", output.display(), e);
+            fail();
+        }
+        println!(
+            "{} → {} ({}µs)",
+            input.display(),
+            output.display(),
+            start.elapsed().as_micros()
+        );
+    }
+}
+
+fn write_blocks<R: Read>(
+    blocks: &mut BlockReader<R>,
+    emitter: &dyn Emitter,
+    mut writer: impl Write,
+) -> Result<(), ReadError> {
+    let mut emitter = StreamingEmitter::new(emitter);
+    for block in blocks {
+        writer.write_all(emitter.emit(block?).as_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn markdown_import(import: Import) {
+    let Import {
+        input,
+        out,
+        extensions:
+            Extensions {
+                bold,
+                italics,
+                list,
+                ordered_lists,
+            },
+        comments,
+    } = import;
+
+    let output = out.unwrap_or_else(|| {
+        let mut output = input.clone();
+        output.set_extension("publ");
+        output
+    });
+
+    if output == input {
+        eprintln!("Refusing to overwrite {} with its own import.", input.display());
+        return;
+    }
+
+    let markdown = match read_to_string(&input) {
         Err(e) => {
-            eprintln!("Failed to parse {}: {}", input.display(), e);
+            eprintln!("Could not read {}: {}", input.display(), e);
             return;
         }
+        Ok(markdown) => markdown,
     };
 
-    match write(&output, emitted) {
-        Err(e) => {
-            eprintln!("Could not write to {}: {}", output.display(), e);
-            return;
+    let mut importer = MarkdownImporter::new();
+    importer.comment_syntax(comments.syntax());
+
+    if bold {
+        importer.bold();
+    }
+
+    if italics {
+        importer.italics();
+    }
+
+    if let Some(bullet) = list {
+        importer.lists(bullet);
+    }
+
+    if ordered_lists {
+        importer.ordered_lists();
+    }
+
+    let import = importer.import(markdown.as_ref());
+
+    for loss in import.losses.iter() {
+        match loss.extension {
+            Some(extension) => eprintln!(
+                "{}:{}: {} was kept as plain text (enable the {} extension to keep it)",
+                input.display(),
+                loss.line,
+                loss.construct,
+                extension
+            ),
+            None => eprintln!(
+                "{}:{}: {} is not supported by Publication",
+                input.display(),
+                loss.line,
+                loss.construct
+            ),
         }
-        Ok(()) => {}
+    }
+
+    if let Err(e) = write(&output, import.source) {
+        eprintln!("Could not write to {}: {}", output.display(), e);
+        return;
     }
 
     println!(
-        "{} → {} ({}µs)",
+        "{} → {} ({} constructs lost)",
         input.display(),
         output.display(),
-        start.elapsed().as_micros()
+        import.losses.len()
     );
 }
+
+fn format(fmt: Fmt) {
+    let Fmt {
+        inputs,
+        check,
+        width,
+        extensions,
+        comments,
+    } = fmt;
+
+    let dialect = dialect(extensions, comments);
+    let mut failed = false;
+
+    for input in inputs {
+        let raw = match read_to_string(&input) {
+            Err(e) => {
+                eprintln!("Could not read {}: {}", input.display(), e);
+                failed = true;
+                continue;
+            }
+            Ok(raw) => raw,
+        };
+
+        let mut emitter = dialect.publication_emitter();
+        emitter.wrap_at(width);
+
+        let (blocks, trivia) = match dialect.parse_lossless(&raw) {
+            Err(diagnostic) => {
+                eprintln!("{}", diagnostic.render(&input.display().to_string(), &raw));
+                eprintln!("Failed to parse {}.", input.display());
+                failed = true;
+                continue;
+            }
+            Ok(parsed) => parsed,
+        };
+
+        let formatted = emitter.print_document(&blocks, &trivia);
+
+        if formatted == raw {
+            continue;
+        }
+
+        if check {
+            eprintln!("{} is not formatted.", input.display());
+            failed = true;
+            continue;
+        }
+
+        if let Err(e) = write(&input, formatted) {
+            eprintln!("Could not write to {}: {}", input.display(), e);
+            failed = true;
+            continue;
+        }
+
+        println!("Formatted {}", input.display());
+    }
+
+    if failed {
+        exit(1);
+    }
+}
@@ -0,0 +1,62 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use publication::{extensions, Dialect, HtmlEmitter};
+
+const SECTION: &str = "# A comment before the section\n\
+Some text with *bold*, /italic/ and */both/* styles, spread over\n\
+a couple of lines   with   uneven spacing, and an escaped \\* star.\n\
+\n\
+- A list item\n  \
+  - A nested item with *bold* text\n\
+- Another item\n\
+\n\
+1. First\n\
+2. Second, with /italics/\n\
+\n\
+A longer paragraph of plain text, without any syntax at all, which is how most\n\
+of a typical document reads. It goes on for a few lines, so that the cost of\n\
+plain text dominates, as it does in practice.\n\
+\n";
+
+// About four megabytes of source.
+fn document() -> String {
+    SECTION.repeat(4 * 1024 * 1024 / SECTION.len())
+}
+
+fn dialect() -> Dialect {
+    let mut dialect = Dialect::new();
+    dialect.add_extension(extensions::Bold);
+    dialect.add_extension(extensions::Italics);
+    dialect.add_extension(extensions::Lists::new("-"));
+    dialect.add_extension(extensions::OrderedLists);
+    dialect
+}
+
+// The same dialect, with many more extensions that never match. Since they
+// declare their trigger characters, they should not slow parsing down.
+fn crowded_dialect() -> Dialect {
+    let mut dialect = dialect();
+    for bullet in '\u{2600}'..'\u{2640}' {
+        dialect.add_extension(extensions::Lists::new(bullet.to_string()));
+    }
+    dialect
+}
+
+fn parse(c: &mut Criterion) {
+    let source = document();
+    let plain = Dialect::new();
+    let dialect = dialect();
+    let crowded = crowded_dialect();
+    let emitter = HtmlEmitter::new();
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.sample_size(10);
+    group.bench_function("without extensions", |b| b.iter(|| plain.parse(&source).unwrap()));
+    group.bench_function("with extensions", |b| b.iter(|| dialect.parse(&source).unwrap()));
+    group.bench_function("with unused extensions", |b| b.iter(|| crowded.parse(&source).unwrap()));
+    group.bench_function("to html", |b| b.iter(|| dialect.emit(&source, &emitter).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
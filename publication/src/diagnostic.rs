@@ -0,0 +1,282 @@
+use crate::*;
+use std::error::Error;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub struct DiagnosticCode(Name);
+
+impl DiagnosticCode {
+    pub const fn from_static(name: &'static str) -> DiagnosticCode {
+        DiagnosticCode(Name::from_static(name))
+    }
+
+    pub fn new(name: &str) -> DiagnosticCode {
+        DiagnosticCode(Name::new(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0.as_str()
+    }
+}
+
+impl fmt::Debug for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => f.write_str("error"),
+            Severity::Warning => f.write_str("warning"),
+        }
+    }
+}
+
+pub const UNEXPECTED_END_OF_FILE: DiagnosticCode = DiagnosticCode::from_static("builtin:UNEXPECTED_END_OF_FILE");
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<DiagnosticCode>,
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+    /// What the parser was looking for where the diagnostic was reported, like
+    /// `` `*` `` or `a paragraph`, for tools that offer completions or fixes.
+    pub expected: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error<M: Into<String>>(span: Span, message: M) -> Diagnostic {
+        Diagnostic::new(Severity::Error, span, message)
+    }
+
+    pub fn warning<M: Into<String>>(span: Span, message: M) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, span, message)
+    }
+
+    pub fn new<M: Into<String>>(severity: Severity, span: Span, message: M) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code: None,
+            message: message.into(),
+            span,
+            notes: vec![],
+            help: None,
+            expected: vec![],
+        }
+    }
+
+    pub fn with_code(mut self, code: DiagnosticCode) -> Diagnostic {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_note<N: Into<String>>(mut self, note: N) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help<H: Into<String>>(mut self, help: H) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_expected<E: Into<String>>(mut self, expected: E) -> Diagnostic {
+        self.expected.push(expected.into());
+        self
+    }
+
+    /// Renders the diagnostic together with an excerpt of `source`, with the
+    /// offending span underlined, in the style of rustc.
+    pub fn render(&self, name: &str, source: &str) -> String {
+        let mut out = String::new();
+
+        out.push_str(format!("{}", self.severity).as_ref());
+        if let Some(code) = self.code {
+            out.push_str(format!("[{}]", code).as_ref());
+        }
+        out.push_str(format!(": {}\n", self.message).as_ref());
+
+        let lines: Vec<&str> = source.lines().collect();
+        // A span that runs up to the start of a line underlines nothing on it.
+        let last_line = match self.span.end.column {
+            1 if self.span.end.line > self.span.start.line => self.span.end.line - 1,
+            _ => self.span.end.line.max(self.span.start.line),
+        };
+        let gutter = last_line.to_string().len();
+        let blank = " ".repeat(gutter);
+
+        out.push_str(format!("{}--> {}:{}\n", blank, name, self.span.start).as_ref());
+        out.push_str(format!("{} |\n", blank).as_ref());
+
+        for line in self.span.start.line..=last_line {
+            let text = lines.get(line - 1).cloned().unwrap_or("");
+            let chars = text.chars().count();
+
+            let from = if line == self.span.start.line {
+                self.span.start.column - 1
+            } else {
+                0
+            };
+            let to = if line == self.span.end.line {
+                self.span.end.column - 1
+            } else {
+                chars
+            };
+            let before: String = text.chars().take(from).collect();
+            let underlined: String = text.chars().skip(from).take(to.saturating_sub(from)).collect();
+
+            out.push_str(format!("{:>gutter$} | {}\n", line, expand_tabs(text), gutter = gutter).as_ref());
+            out.push_str(
+                format!(
+                    "{} | {}{}\n",
+                    blank,
+                    " ".repeat(expand_tabs(&before).width()),
+                    "^".repeat(expand_tabs(&underlined).width().max(1))
+                )
+                .as_ref(),
+            );
+        }
+
+        if !self.notes.is_empty() || self.help.is_some() {
+            out.push_str(format!("{} |\n", blank).as_ref());
+        }
+        for note in self.notes.iter() {
+            out.push_str(format!("{} = note: {}\n", blank, note).as_ref());
+        }
+        if let Some(help) = &self.help {
+            out.push_str(format!("{} = help: {}\n", blank, help).as_ref());
+        }
+
+        out
+    }
+}
+
+/// Tabs are echoed as four spaces, so that the carets below them line up no
+/// matter how the terminal shows tabs.
+fn expand_tabs(text: &str) -> Cow<'_, str> {
+    match text.contains('\t') {
+        true => Cow::Owned(text.replace('\t', "    ")),
+        false => Cow::Borrowed(text),
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(code) = self.code {
+            write!(f, "[{}] ", code)?;
+        }
+        write!(f, "{} at {}", self.message, self.span.start)
+    }
+}
+
+impl Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_source_excerpt() {
+        const UNCLOSED: DiagnosticCode = DiagnosticCode::from_static("MY_UNCLOSED");
+
+        let source = "First paragraph.\n\nThis *isn't\nclosed.\n";
+        let diagnostic = Diagnostic::error(
+            Span::new(Position::new(23, 3, 6), Position::new(24, 3, 7)),
+            "unclosed `*`",
+        )
+        .with_code(UNCLOSED)
+        .with_note("the paragraph ends before a closing `*`")
+        .with_help("add a `*` after the emphasized text");
+
+        assert_eq!(
+            diagnostic.render("example.publ", source),
+            "error[MY_UNCLOSED]: unclosed `*`\n \
+             --> example.publ:3:6\n  \
+             |\n\
+             3 | This *isn't\n  \
+             |      ^\n  \
+             |\n  \
+             = note: the paragraph ends before a closing `*`\n  \
+             = help: add a `*` after the emphasized text\n"
+        );
+    }
+
+    #[test]
+    fn renders_warnings() {
+        let source = "Hello";
+        let diagnostic = Diagnostic::warning(
+            Span::new(Position::new(0, 1, 1), Position::new(5, 1, 6)),
+            "greeting",
+        );
+
+        assert_eq!(
+            diagnostic.render("example.publ", source),
+            "warning: greeting\n \
+             --> example.publ:1:1\n  \
+             |\n\
+             1 | Hello\n  \
+             | ^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn renders_spans_across_lines() {
+        let source = "Some *bold\ntext* here.";
+        let diagnostic = Diagnostic::error(
+            Span::new(Position::new(5, 1, 6), Position::new(16, 2, 6)),
+            "something is wrong",
+        );
+
+        assert_eq!(
+            diagnostic.render("example.publ", source),
+            "error: something is wrong\n \
+             --> example.publ:1:6\n  \
+             |\n\
+             1 | Some *bold\n  \
+             |      ^^^^^\n\
+             2 | text* here.\n  \
+             | ^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn underlines_by_display_width() {
+        let source = "\tlet 名前 = *x\nnext\n";
+        let diagnostic = Diagnostic::error(Span::new(Position::new(5, 1, 6), Position::new(11, 1, 8)), "wide");
+        assert_eq!(
+            diagnostic.render("tabs.publ", source),
+            "error: wide\n \
+             --> tabs.publ:1:6\n  \
+             |\n\
+             1 |     let 名前 = *x\n  \
+             |         ^^^^\n"
+        );
+
+        let diagnostic = Diagnostic::error(Span::new(Position::new(5, 1, 6), Position::new(17, 2, 1)), "to the end");
+        assert_eq!(
+            diagnostic.render("tabs.publ", source),
+            "error: to the end\n \
+             --> tabs.publ:1:6\n  \
+             |\n\
+             1 |     let 名前 = *x\n  \
+             |         ^^^^^^^^^\n"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use crate::*;
+use std::io;
+
+/// The extensions and options of a parser, kept apart from any document so
+/// that it can be set up once and then used to parse any number of
+/// documents. Dialects can be cloned or shared between threads.
+#[derive(Clone, Default)]
+pub struct Dialect {
+    extensions: Extensions,
+    comment_syntax: CommentSyntax,
+    deny_warnings: bool,
+}
+
+impl Dialect {
+    pub fn new() -> Dialect {
+        Dialect::default()
+    }
+
+    pub fn add_extension<E: Extension + 'static>(&mut self, extension: E) {
+        Arc::make_mut(&mut self.extensions).push(Arc::new(extension));
+    }
+
+    pub fn comment_syntax(&mut self, syntax: CommentSyntax) {
+        self.comment_syntax = syntax;
+    }
+
+    /// Applies `Parser::deny_warnings` to every parser of this dialect.
+    pub fn deny_warnings(&mut self) {
+        self.deny_warnings = true;
+    }
+
+    /// A parser for `source` in this dialect. The extensions are shared with
+    /// the dialect rather than copied.
+    pub fn parser<'a>(&self, source: &'a str) -> Parser<'a> {
+        let mut parser = Parser::new(source);
+        parser.extensions = self.extensions.clone();
+        parser.comment_syntax(self.comment_syntax.clone());
+        if self.deny_warnings {
+            parser.deny_warnings();
+        }
+        parser
+    }
+
+    pub fn parse<'a>(&self, source: &'a str) -> ParseResult<Vec<Block<'a>>> {
+        self.parser(source).parse()
+    }
+
+    pub fn parse_lossless<'a>(&self, source: &'a str) -> ParseResult<(Vec<Block<'a>>, Trivia)> {
+        self.parser(source).parse_lossless()
+    }
+
+    pub fn parse_recovering<'a>(&self, source: &'a str) -> (Vec<Block<'a>>, Vec<Diagnostic>) {
+        self.parser(source).parse_recovering()
+    }
+
+    pub fn emit(&self, source: &str, emitter: &dyn Emitter) -> ParseResult<String> {
+        self.parser(source).emit_with(emitter)
+    }
+
+    pub fn emit_recovering(&self, source: &str, emitter: &dyn Emitter) -> (String, Vec<Diagnostic>) {
+        self.parser(source).emit_recovering(emitter)
+    }
+
+    pub fn emit_to<W: io::Write>(&self, source: &str, emitter: &dyn Emitter, writer: W) -> Result<(), EmitError> {
+        self.parser(source).emit_to(emitter, writer)
+    }
+
+    /// Parses blocks from `reader` as it is read, without reading all of it
+    /// into memory first.
+    pub fn read_blocks<R: io::Read>(&self, reader: R) -> BlockReader<R> {
+        BlockReader::new(reader, self.clone())
+    }
+
+    /// Parses `source` into a document that can be edited without having to
+    /// be parsed again from the start.
+    pub fn document<S: Into<String>>(&self, source: S) -> Document {
+        Document::new(self.clone(), source.into())
+    }
+
+    /// A `PublicationEmitter` that prints documents of this dialect.
+    pub fn publication_emitter(&self) -> PublicationEmitter {
+        let mut emitter = PublicationEmitter::with_extensions(self.extensions.clone());
+        emitter.comment_syntax(self.comment_syntax.clone());
+        emitter
+    }
+}
@@ -0,0 +1,127 @@
+use crate::*;
+use std::ops::Range;
+
+/// A parsed document that is kept up to date as its source is edited, as
+/// made by `Dialect::document`. Since blocks are separated by blank lines, an
+/// edit only has to reparse the blocks around it; the blocks before and after
+/// those are kept, with their spans moved along with the source.
+///
+/// Like `Parser::parse_recovering`, blocks that fail to parse are left out,
+/// and their errors are kept as `diagnostics`, since a document is bound to
+/// be broken halfway through being typed.
+pub struct Document {
+    dialect: Dialect,
+    source: String,
+    blocks: Vec<Block<'static>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    pub(crate) fn new(dialect: Dialect, source: String) -> Document {
+        let (blocks, diagnostics) = dialect.parse_recovering(&source);
+        let blocks = blocks.into_iter().map(Block::into_owned).collect();
+        Document {
+            dialect,
+            source,
+            blocks,
+            diagnostics,
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn blocks(&self) -> &[Block<'static>] {
+        &self.blocks
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Replaces the bytes of the source in `range` with `text`, and reparses
+    /// the blocks that the edit could have changed. Returns the range of
+    /// `blocks` that were reparsed.
+    ///
+    /// Panics if `range` is out of bounds or not on character boundaries,
+    /// like `String::replace_range`.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) -> Range<usize> {
+        let ends_line = |source: &str, end: usize| end == 0 || source.as_bytes()[end - 1] == b'\n';
+        let removed_lines = self.source[range.clone()].matches('\n').count();
+        let removed_to_end_of_line = ends_line(&self.source, range.end);
+        self.source.replace_range(range.clone(), text);
+        let offset_delta = text.len() as isize - range.len() as isize;
+        let line_delta = text.matches('\n').count() as isize - removed_lines as isize;
+        let edit_end = range.start + text.len();
+
+        // Typing at the end of a block, or in the blank lines after it, can
+        // extend it, so reparsing starts after the block before that.
+        let first = self.blocks.partition_point(|block| block.span().end.offset < range.start);
+        let first = first.saturating_sub(1);
+        let start = match first {
+            0 => Position::default(),
+            _ => self.blocks[first - 1].span().end,
+        };
+
+        // Once a block starts where one did before the edit, on a line that
+        // starts after the edit both before and after it, it and everything
+        // after it parse the same way as before.
+        let next_line = match removed_to_end_of_line && ends_line(&self.source, edit_end) {
+            true => edit_end,
+            false => self.source[edit_end..].find('\n').map_or(usize::MAX, |i| edit_end + i + 1),
+        };
+        let old_start = |offset: usize| (offset as isize - offset_delta) as usize;
+        let mut parser = self.dialect.parser(&self.source);
+        parser.position = start;
+        parser.content_end = start;
+        let mut reparsed = vec![];
+        let mut reused = None;
+
+        parser.move_past_whitespace();
+        while !parser.is_at_end() {
+            let offset = parser.position.offset;
+            if offset >= next_line {
+                let old = self.blocks.partition_point(|block| block.span().start.offset < old_start(offset));
+                if self.blocks.get(old).is_some_and(|block| block.span().start.offset == old_start(offset)) {
+                    reused = Some(old);
+                    break;
+                }
+            }
+            reparsed.extend(parser.parse_block_recovering().map(Block::into_owned));
+            parser.move_past_whitespace();
+        }
+
+        let shift = |position: Position| {
+            Position::new(
+                (position.offset as isize + offset_delta) as usize,
+                (position.line as isize + line_delta) as usize,
+                position.column,
+            )
+        };
+        let reused = reused.unwrap_or(self.blocks.len());
+        let reused_from = self.blocks.get(reused).map_or(usize::MAX, |block| block.span().start.offset);
+
+        let mut diagnostics = vec![];
+        let mut after = vec![];
+        for mut diagnostic in self.diagnostics.drain(..) {
+            if diagnostic.span.start.offset < start.offset {
+                diagnostics.push(diagnostic);
+            } else if diagnostic.span.start.offset >= reused_from {
+                diagnostic.span = diagnostic.span.map(shift);
+                after.push(diagnostic);
+            }
+        }
+        diagnostics.append(&mut parser.diagnostics);
+        diagnostics.append(&mut after);
+        self.diagnostics = diagnostics;
+
+        let mut after = self.blocks.split_off(reused);
+        after.iter_mut().for_each(|block| block.map_positions(&shift));
+        self.blocks.truncate(first);
+        let reparsed_blocks = first..first + reparsed.len();
+        self.blocks.append(&mut reparsed);
+        self.blocks.append(&mut after);
+        reparsed_blocks
+    }
+}
@@ -1,5 +1,16 @@
 use crate::*;
 use std::collections::HashMap;
+use std::sync::Arc;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+mod markdown;
+pub use self::markdown::*;
+
+mod publication;
+pub use self::publication::*;
+
+mod stream;
+pub use self::stream::*;
 
 pub trait Emitter {
     fn emit_block(&self, block: Block, out: &mut String);
@@ -12,17 +23,17 @@ pub trait Emitter {
 
     fn emit_element(&self, element: Element, out: &mut String) {
         match element {
-            Element::Text(t) => self.emit_text(t, out),
-            Element::ExtensionElement(tag, e) => self.emit_extension_element(tag, *e, out),
+            Element::Text(t, _) => self.emit_text(t.as_ref(), out),
+            Element::ExtensionElement(tag, e, _) => self.emit_extension_element(tag, e, out),
         }
     }
 
-    fn emit_text(&self, text: String, out: &mut String) {
-        out.push_str(text.as_ref());
+    fn emit_text(&self, text: &str, out: &mut String) {
+        out.push_str(text);
     }
 
-    fn emit_extension_element(&self, _tag: ExtensionTag, element: Element, out: &mut String) {
-        self.emit_element(element, out)
+    fn emit_extension_element(&self, _tag: ExtensionTag, elements: Vec<Element>, out: &mut String) {
+        self.emit_elements(elements, out)
     }
 }
 
@@ -33,15 +44,22 @@ impl TryInto<Box<dyn Emitter>> for &Path {
         match self.extension() {
             Some(html) if html == "html" => Ok(Box::new(HtmlEmitter::new())),
             Some(txt) if txt == "txt" => Ok(Box::new(TextEmitter::new())),
+            Some(md) if md == "md" => Ok(Box::new(MarkdownEmitter::new())),
             _ => Err(()),
         }
     }
 }
 
-pub(crate) struct HtmlEmitter {
-    extension_element_map: HashMap<ExtensionTag, Box<dyn Fn(&Element) -> (String, Vec<(String, String)>)>>,
-    extension_block_map: HashMap<ExtensionTag, Box<dyn Fn(&Vec<Element>) -> (String, Vec<(String, String)>)>>,
-    extension_blocks_map: HashMap<ExtensionTag, Box<dyn Fn(&Vec<Block>) -> (String, Vec<(String, String)>)>>,
+type HtmlTag = (String, Vec<(String, String)>);
+type ElementTagFn = Arc<dyn Fn(&Vec<Element>) -> HtmlTag + Send + Sync>;
+type BlockTagFn = Arc<dyn Fn(&Vec<Element>) -> HtmlTag + Send + Sync>;
+type BlocksTagFn = Arc<dyn Fn(&Vec<Block>) -> HtmlTag + Send + Sync>;
+
+#[derive(Clone)]
+pub struct HtmlEmitter {
+    extension_element_map: HashMap<ExtensionTag, ElementTagFn>,
+    extension_block_map: HashMap<ExtensionTag, BlockTagFn>,
+    extension_blocks_map: HashMap<ExtensionTag, BlocksTagFn>,
 }
 
 impl HtmlEmitter {
@@ -54,34 +72,40 @@ impl HtmlEmitter {
     }
 }
 
+impl Default for HtmlEmitter {
+    fn default() -> HtmlEmitter {
+        HtmlEmitter::new()
+    }
+}
+
 impl HtmlEmitter {
-    pub fn tagged_element<F: 'static + Fn(&Element) -> (String, Vec<(String, String)>)>(&mut self, tag: ExtensionTag, f: F) {
-        self.extension_element_map.insert(tag, Box::new(f));
+    pub fn tagged_element<F: 'static + Fn(&Vec<Element>) -> (String, Vec<(String, String)>) + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.extension_element_map.insert(tag, Arc::new(f));
     }
 
-    pub fn tagged_block<F: 'static + Fn(&Vec<Element>) -> (String, Vec<(String, String)>)>(&mut self, tag: ExtensionTag, f: F) {
-        self.extension_block_map.insert(tag, Box::new(f));
+    pub fn tagged_block<F: 'static + Fn(&Vec<Element>) -> (String, Vec<(String, String)>) + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.extension_block_map.insert(tag, Arc::new(f));
     }
 
-    pub fn tagged_blocks<F: 'static + Fn(&Vec<Block>) -> (String, Vec<(String, String)>)>(&mut self, tag: ExtensionTag, f: F) {
-        self.extension_blocks_map.insert(tag, Box::new(f));
+    pub fn tagged_blocks<F: 'static + Fn(&Vec<Block>) -> (String, Vec<(String, String)>) + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.extension_blocks_map.insert(tag, Arc::new(f));
     }
 }
 
 impl Emitter for HtmlEmitter {
     fn emit_block(&self, block: Block, out: &mut String) {
         match block {
-            Block::Paragraph(e) => {
+            Block::Paragraph(e, _) => {
                 out.push_str("<p>\n  ");
                 self.emit_elements(e, out);
                 out.push_str("\n</p>\n");
             }
-            Block::ExtensionBlock(extensions::LIST_ITEM, elements) => {
+            Block::ExtensionBlock(extensions::LIST_ITEM, elements, _) => {
                 out.push_str("  <li>\n    ");
                 self.emit_elements(elements, out);
                 out.push_str("\n  </li>\n");
             }
-            Block::ExtensionBlock(tag, elements) => {
+            Block::ExtensionBlock(tag, elements, _) => {
                 let ext = self.extension_block_map.get(&tag).map(|f| f(&elements));
                 if let Some((element, attrs)) = &ext {
                     out.push_str(format!("<{}", element).as_ref());
@@ -101,14 +125,52 @@ impl Emitter for HtmlEmitter {
                     out.push_str("\n</div>\n");
                 }
             }
-            Block::ExtensionBlocks(extensions::LIST, blocks) => {
+            Block::ExtensionBlocks(extensions::LIST_ITEM, blocks, _, _) => {
+                let paragraphs = blocks.iter().filter(|b| matches!(b, Block::Paragraph(..))).count();
+                out.push_str("  <li>\n");
+                for block in blocks {
+                    match block {
+                        // Items with a single paragraph don't get wrapped in <p>.
+                        Block::Paragraph(elements, _) if paragraphs == 1 => {
+                            out.push_str("    ");
+                            self.emit_elements(elements, out);
+                            out.push('\n');
+                        }
+                        block => {
+                            let mut inner = String::new();
+                            self.emit_block(block, &mut inner);
+                            for line in inner.lines() {
+                                out.push_str("    ");
+                                out.push_str(line);
+                                out.push('\n');
+                            }
+                        }
+                    }
+                }
+                out.push_str("  </li>\n");
+            }
+            Block::ExtensionBlocks(extensions::LIST, blocks, _, _) => {
                 out.push_str("<ul>\n");
                 for block in blocks {
                     self.emit_block(block, out);
                 }
                 out.push_str("</ul>\n");
             }
-            Block::ExtensionBlocks(tag, blocks) => {
+            Block::ExtensionBlocks(extensions::ORDERED_LIST, blocks, attributes, _) => {
+                out.push_str("<ol");
+                for (key, value) in attributes.iter() {
+                    match (key.as_ref(), value.as_ref()) {
+                        ("start", "1") | ("type", "1") | ("delimiter", _) => {}
+                        (key, value) => out.push_str(format!(" {}={:?}", key, value).as_ref()),
+                    }
+                }
+                out.push_str(">\n");
+                for block in blocks {
+                    self.emit_block(block, out);
+                }
+                out.push_str("</ol>\n");
+            }
+            Block::ExtensionBlocks(tag, blocks, _, _) => {
                 let ext = self.extension_blocks_map.get(&tag).map(|f| f(&blocks));
                 if let Some((element, attrs)) = &ext {
                     out.push_str(format!("<{}", element).as_ref());
@@ -127,7 +189,7 @@ impl Emitter for HtmlEmitter {
         }
     }
 
-    fn emit_text(&self, text: String, out: &mut String) {
+    fn emit_text(&self, text: &str, out: &mut String) {
         for c in text.chars() {
             match c {
                 '\'' => out.push_str("&apos;"),
@@ -140,53 +202,417 @@ impl Emitter for HtmlEmitter {
         }
     }
 
-    fn emit_extension_element(&self, tag: ExtensionTag, element: Element, out: &mut String) {
+    fn emit_extension_element(&self, tag: ExtensionTag, elements: Vec<Element>, out: &mut String) {
         match tag {
             extensions::BOLD => {
                 out.push_str("<strong>");
-                self.emit_element(element, out);
+                self.emit_elements(elements, out);
                 out.push_str("</strong>");
             }
             extensions::ITALICS => {
                 out.push_str("<em>");
-                self.emit_element(element, out);
+                self.emit_elements(elements, out);
                 out.push_str("</em>");
             }
             tag if self.extension_element_map.contains_key(&tag) => {
-                let (el, attrs) = self.extension_element_map.get(&tag).unwrap()(&element);
+                let (el, attrs) = self.extension_element_map.get(&tag).unwrap()(&elements);
                 out.push_str(format!("<{}", el).as_ref());
                 for (key, value) in attrs.iter() {
                     out.push_str(format!(" {}={:?}", key, value).as_ref());
                 }
                 out.push('>');
-                self.emit_element(element, out);
+                self.emit_elements(elements, out);
                 out.push_str(format!("</{}>", el).as_ref());
             }
-            _ => self.emit_element(element, out),
+            _ => self.emit_elements(elements, out),
         }
     }
 }
 
-pub(crate) struct TextEmitter {
+/// How `TextEmitter` and `MarkdownEmitter` should present the output of a
+/// tagged element or block. `indent` is put in front of every line, and so
+/// only applies to blocks. A format registered for a tag with `tagged_element`
+/// and friends takes precedence over the built-in rendering of that tag.
+#[derive(Default)]
+pub struct TextFormat {
+    pub prefix: String,
+    pub suffix: String,
+    pub indent: String,
+    pub transform: Option<Box<dyn Fn(String) -> String + Send + Sync>>,
+}
+
+impl TextFormat {
+    pub fn new() -> TextFormat {
+        TextFormat::default()
+    }
+
+    pub fn with_prefix<S: Into<String>>(mut self, prefix: S) -> TextFormat {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn with_suffix<S: Into<String>>(mut self, suffix: S) -> TextFormat {
+        self.suffix = suffix.into();
+        self
+    }
+
+    pub fn with_indent<S: Into<String>>(mut self, indent: S) -> TextFormat {
+        self.indent = indent.into();
+        self
+    }
+
+    pub fn with_transform<F: 'static + Fn(String) -> String + Send + Sync>(mut self, transform: F) -> TextFormat {
+        self.transform = Some(Box::new(transform));
+        self
+    }
+
+    fn apply(&self, text: String) -> String {
+        let text = match &self.transform {
+            Some(transform) => transform(text),
+            None => text,
+        };
+        format!("{}{}{}", self.prefix, text, self.suffix)
+    }
+
+    fn apply_to_lines(&self, mut text: String, out: &mut String) {
+        text.truncate(text.trim_end_matches('\n').len());
+        for line in self.apply(text).lines() {
+            if !line.is_empty() {
+                out.push_str(self.indent.as_ref());
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+}
+
+type ElementFormatFn = Arc<dyn Fn(&Vec<Element>) -> TextFormat + Send + Sync>;
+type BlockFormatFn = Arc<dyn Fn(&Vec<Element>) -> TextFormat + Send + Sync>;
+type BlocksFormatFn = Arc<dyn Fn(&Vec<Block>) -> TextFormat + Send + Sync>;
+
+// The formats registered for tags, shared by the emitters that take a `TextFormat`.
+#[derive(Clone, Default)]
+struct TextFormats {
+    element_map: HashMap<ExtensionTag, ElementFormatFn>,
+    block_map: HashMap<ExtensionTag, BlockFormatFn>,
+    blocks_map: HashMap<ExtensionTag, BlocksFormatFn>,
+}
+
+impl TextFormats {
+    fn tagged_element<F: 'static + Fn(&Vec<Element>) -> TextFormat + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.element_map.insert(tag, Arc::new(f));
+    }
+
+    fn tagged_block<F: 'static + Fn(&Vec<Element>) -> TextFormat + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.block_map.insert(tag, Arc::new(f));
+    }
+
+    fn tagged_blocks<F: 'static + Fn(&Vec<Block>) -> TextFormat + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.blocks_map.insert(tag, Arc::new(f));
+    }
+}
+
+#[derive(Clone)]
+pub struct TextEmitter {
+    formats: TextFormats,
+    width: Option<usize>,
+    justify: bool,
+    // Columns taken up by the markers and indents of enclosing blocks.
+    indent: usize,
 }
 
 impl TextEmitter {
     pub fn new() -> TextEmitter {
-        TextEmitter {}
+        TextEmitter {
+            formats: TextFormats::default(),
+            width: None,
+            justify: false,
+            indent: 0,
+        }
+    }
+
+    /// Wraps paragraphs so that no line is wider than `width` columns,
+    /// including the indentation of list items and tagged blocks.
+    pub fn wrap_at(&mut self, width: usize) {
+        self.width = Some(width);
+    }
+
+    /// Pads wrapped lines with extra spaces so that they are all as wide as
+    /// the wrap width. The last line of every paragraph is left as is.
+    pub fn justify(&mut self) {
+        self.justify = true;
+    }
+}
+
+impl TextEmitter {
+    pub fn tagged_element<F: 'static + Fn(&Vec<Element>) -> TextFormat + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.formats.tagged_element(tag, f);
+    }
+
+    pub fn tagged_block<F: 'static + Fn(&Vec<Element>) -> TextFormat + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.formats.tagged_block(tag, f);
+    }
+
+    pub fn tagged_blocks<F: 'static + Fn(&Vec<Block>) -> TextFormat + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.formats.tagged_blocks(tag, f);
+    }
+}
+
+impl Default for TextEmitter {
+    fn default() -> TextEmitter {
+        TextEmitter::new()
+    }
+}
+
+impl TextEmitter {
+    fn emit_paragraph(&self, text: String, out: &mut String) {
+        match self.width {
+            Some(width) => {
+                let width = width.saturating_sub(self.indent).max(1);
+                for line in wrap(text.as_ref(), width, self.justify) {
+                    out.push_str(line.as_ref());
+                    out.push('\n');
+                }
+            }
+            None => {
+                out.push_str(text.as_ref());
+                out.push('\n');
+            }
+        }
+    }
+
+    // An emitter for content that is indented `indent` more columns.
+    fn indented(&self, indent: usize) -> TextEmitter {
+        TextEmitter {
+            indent: self.indent + indent,
+            ..self.clone()
+        }
+    }
+
+    fn emit_list_item(&self, marker: &str, item: Block, out: &mut String) {
+        let mut inner = String::new();
+        emit_list_item_blocks(&self.indented(marker.width() + 1), item, &mut inner);
+        push_hanging(marker, inner.as_ref(), out);
+    }
+}
+
+fn emit_list_item_blocks<E: Emitter + ?Sized>(emitter: &E, item: Block, out: &mut String) {
+    let blocks = match item {
+        Block::ExtensionBlocks(_, blocks, _, _) => blocks,
+        Block::ExtensionBlock(_, e, span) => vec![Block::Paragraph(e, span)],
+        block => vec![block],
+    };
+
+    for block in blocks {
+        match block {
+            // Sub-lists follow their parent item without a blank line.
+            Block::ExtensionBlocks(extensions::LIST, ..)
+            | Block::ExtensionBlocks(extensions::ORDERED_LIST, ..) => {
+                let mut list = String::new();
+                emitter.emit_block(block, &mut list);
+                out.push_str(list.as_ref());
+            }
+            block => emitter.emit_block(block, out),
+        }
+    }
+}
+
+/// Puts `marker` in front of the first line of `inner`, and indents the rest
+/// of the lines to line up with the text after it.
+fn push_hanging(marker: &str, inner: &str, out: &mut String) {
+    let indent = " ".repeat(marker.width() + 1);
+    for (i, line) in inner.lines().enumerate() {
+        if i == 0 {
+            out.push_str(marker);
+            out.push(' ');
+        } else if !line.is_empty() {
+            out.push_str(indent.as_ref());
+        }
+        out.push_str(line);
+        out.push('\n');
     }
 }
 
 impl Emitter for TextEmitter {
     fn emit_block(&self, block: Block, out: &mut String) {
         match block {
-            Block::Paragraph(e) => {
+            Block::Paragraph(e, _) => {
                 if !out.is_empty() {
                     out.push('\n');
                 }
-                self.emit_elements(e, out);
-                out.push('\n');
+                let mut text = String::new();
+                self.emit_elements(e, &mut text);
+                self.emit_paragraph(text, out);
+            }
+            Block::ExtensionBlock(tag, elements, _) if self.formats.block_map.contains_key(&tag) => {
+                let format = self.formats.block_map[&tag](&elements);
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                let mut text = String::new();
+                self.emit_elements(elements, &mut text);
+                let mut inner = String::new();
+                self.indented(format.indent.width()).emit_paragraph(text, &mut inner);
+                format.apply_to_lines(inner, out);
+            }
+            Block::ExtensionBlocks(tag, blocks, _, _) if self.formats.blocks_map.contains_key(&tag) => {
+                let format = self.formats.blocks_map[&tag](&blocks);
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                let mut inner = String::new();
+                let indented = self.indented(format.indent.width());
+                for block in blocks {
+                    indented.emit_block(block, &mut inner);
+                }
+                format.apply_to_lines(inner, out);
+            }
+            Block::ExtensionBlocks(extensions::ORDERED_LIST, items, attributes, _) => {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                let numbering = attribute(&attributes, "type")
+                    .and_then(extensions::Numbering::from_html_type)
+                    .unwrap_or(extensions::Numbering::Decimal);
+                let start = attribute(&attributes, "start").and_then(|s| s.parse().ok()).unwrap_or(1);
+                let delimiter = attribute(&attributes, "delimiter").unwrap_or(".");
+                let markers: Vec<String> = (start..start + items.len())
+                    .map(|n| format!("{}{}", numbering.format(n), delimiter))
+                    .collect();
+                let width = markers.iter().map(|m| m.chars().count()).max().unwrap_or(0);
+                for (item, marker) in items.into_iter().zip(markers) {
+                    self.emit_list_item(format!("{:>width$}", marker, width = width).as_ref(), item, out);
+                }
+            }
+            Block::ExtensionBlocks(extensions::LIST, items, _, _) => {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                for item in items {
+                    self.emit_list_item("•", item, out);
+                }
+            }
+            item @ Block::ExtensionBlock(extensions::LIST_ITEM, ..)
+            | item @ Block::ExtensionBlocks(extensions::LIST_ITEM, ..) => {
+                self.emit_list_item("•", item, out);
+            }
+            // Text of blocks that this emitter knows nothing about is kept as is.
+            Block::ExtensionBlock(_, e, span) => self.emit_block(Block::Paragraph(e, span), out),
+            Block::ExtensionBlocks(_, blocks, _, _) => {
+                for block in blocks {
+                    self.emit_block(block, out);
+                }
+            }
+        }
+    }
+
+    fn emit_extension_element(&self, tag: ExtensionTag, elements: Vec<Element>, out: &mut String) {
+        match self.formats.element_map.get(&tag) {
+            Some(f) => {
+                let format = f(&elements);
+                let mut inner = String::new();
+                self.emit_elements(elements, &mut inner);
+                out.push_str(format.apply(inner).as_ref());
+            }
+            None => self.emit_elements(elements, out),
+        }
+    }
+}
+
+// A piece of a line that the wrapping may not break up, along with its
+// display width and whether it is preceded by a space.
+type Unit = (String, usize, bool);
+
+/// Splits text into units that may be put on separate lines. Words are split
+/// around wide characters, since CJK text may be broken between any two
+/// characters, and zero-width characters stay with the character before them.
+fn units(text: &str) -> Vec<Unit> {
+    let mut units: Vec<Unit> = vec![];
+    for word in text.split(' ').filter(|w| !w.is_empty()) {
+        let mut space = true;
+        let mut previous_wide = false;
+        for c in word.chars() {
+            let width = c.width().unwrap_or(0);
+            let wide = width > 1;
+            match units.last_mut() {
+                Some((unit, unit_width, _)) if !space && (width == 0 || !(wide || previous_wide)) => {
+                    unit.push(c);
+                    *unit_width += width;
+                }
+                _ => units.push((c.to_string(), width, space)),
+            }
+            if width > 0 {
+                previous_wide = wide;
+            }
+            space = false;
+        }
+    }
+    units
+}
+
+/// Breaks a unit that is wider than a line on character boundaries.
+fn split_unit((text, _, space): Unit, width: usize) -> Vec<Unit> {
+    let mut pieces: Vec<Unit> = vec![];
+    for c in text.chars() {
+        let c_width = c.width().unwrap_or(0);
+        match pieces.last_mut() {
+            Some((piece, piece_width, _)) if *piece_width + c_width <= width || c_width == 0 => {
+                piece.push(c);
+                *piece_width += c_width;
+            }
+            _ => pieces.push((c.to_string(), c_width, pieces.is_empty() && space)),
+        }
+    }
+    pieces
+}
+
+fn wrap(text: &str, width: usize, justify: bool) -> Vec<String> {
+    let mut lines: Vec<(Vec<Unit>, usize)> = vec![];
+    let mut line: Vec<Unit> = vec![];
+    let mut line_width = 0;
+
+    for unit in units(text) {
+        let pieces = if unit.1 > width {
+            split_unit(unit, width)
+        } else {
+            vec![unit]
+        };
+        for (piece, piece_width, space) in pieces {
+            let needed = if space && !line.is_empty() { 1 } else { 0 } + piece_width;
+            if !line.is_empty() && line_width + needed > width {
+                lines.push((std::mem::take(&mut line), line_width));
+                line_width = 0;
             }
-            _ => {}
+            line_width += if space && !line.is_empty() { 1 } else { 0 } + piece_width;
+            line.push((piece, piece_width, space));
         }
     }
+    if !line.is_empty() {
+        lines.push((line, line_width));
+    }
+
+    let count = lines.len();
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, (line, line_width))| {
+            let gaps = line.iter().skip(1).filter(|(_, _, space)| *space).count();
+            let extra = if justify && i + 1 < count && gaps > 0 {
+                width.saturating_sub(line_width)
+            } else {
+                0
+            };
+            let mut out = String::new();
+            let mut gap = 0;
+            for (j, (piece, _, space)) in line.into_iter().enumerate() {
+                if j > 0 && space {
+                    let padding = extra / gaps + if gap < extra % gaps { 1 } else { 0 };
+                    out.push_str(" ".repeat(1 + padding).as_ref());
+                    gap += 1;
+                }
+                out.push_str(piece.as_ref());
+            }
+            out
+        })
+        .collect()
 }
@@ -0,0 +1,153 @@
+use super::*;
+
+/// Emits CommonMark. Lists keep their nesting, but since Markdown only has
+/// decimal numbering, ordered lists of letters or roman numerals are numbered
+/// with digits from the same start.
+#[derive(Clone)]
+pub struct MarkdownEmitter {
+    formats: TextFormats,
+}
+
+impl MarkdownEmitter {
+    pub fn new() -> MarkdownEmitter {
+        MarkdownEmitter {
+            formats: TextFormats::default(),
+        }
+    }
+}
+
+impl Default for MarkdownEmitter {
+    fn default() -> MarkdownEmitter {
+        MarkdownEmitter::new()
+    }
+}
+
+impl MarkdownEmitter {
+    pub fn tagged_element<F: 'static + Fn(&Vec<Element>) -> TextFormat + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.formats.tagged_element(tag, f);
+    }
+
+    pub fn tagged_block<F: 'static + Fn(&Vec<Element>) -> TextFormat + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.formats.tagged_block(tag, f);
+    }
+
+    pub fn tagged_blocks<F: 'static + Fn(&Vec<Block>) -> TextFormat + Send + Sync>(&mut self, tag: ExtensionTag, f: F) {
+        self.formats.tagged_blocks(tag, f);
+    }
+}
+
+impl MarkdownEmitter {
+    fn emit_paragraph(&self, elements: Vec<Element>, out: &mut String) {
+        let mut text = String::new();
+        self.emit_elements(elements, &mut text);
+        if let Some(i) = block_marker(text.as_ref()) {
+            text.insert(i, '\\');
+        }
+        out.push_str(text.as_ref());
+        out.push('\n');
+    }
+
+    fn emit_list_item(&self, marker: &str, item: Block, out: &mut String) {
+        let mut inner = String::new();
+        emit_list_item_blocks(self, item, &mut inner);
+        push_hanging(marker, inner.as_ref(), out);
+    }
+}
+
+/// Finds the character that would make a paragraph starting with `text` be
+/// read as some other kind of block, like a heading or a list item, so that
+/// it can be escaped.
+fn block_marker(text: &str) -> Option<usize> {
+    let digits = text.chars().take_while(|c| c.is_ascii_digit()).count();
+    let mut rest = text[digits..].chars();
+    let starts_block = match (digits, rest.next(), rest.next()) {
+        (0, Some('#'), _) | (0, Some('='), _) => true,
+        (0, Some('-'), next) | (0, Some('+'), next) => next.map(|c| c == ' ' || c == '-').unwrap_or(true),
+        (1..=9, Some('.'), next) | (1..=9, Some(')'), next) => next.map(|c| c == ' ').unwrap_or(true),
+        _ => false,
+    };
+    if starts_block {
+        Some(digits)
+    } else {
+        None
+    }
+}
+
+impl Emitter for MarkdownEmitter {
+    fn emit_block(&self, block: Block, out: &mut String) {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        match block {
+            Block::Paragraph(e, _) => self.emit_paragraph(e, out),
+            Block::ExtensionBlock(tag, elements, _) if self.formats.block_map.contains_key(&tag) => {
+                let format = self.formats.block_map[&tag](&elements);
+                let mut inner = String::new();
+                self.emit_elements(elements, &mut inner);
+                format.apply_to_lines(inner, out);
+            }
+            Block::ExtensionBlocks(tag, blocks, _, _) if self.formats.blocks_map.contains_key(&tag) => {
+                let format = self.formats.blocks_map[&tag](&blocks);
+                let mut inner = String::new();
+                for block in blocks {
+                    self.emit_block(block, &mut inner);
+                }
+                format.apply_to_lines(inner, out);
+            }
+            Block::ExtensionBlocks(extensions::LIST, items, _, _) => {
+                for item in items {
+                    self.emit_list_item("-", item, out);
+                }
+            }
+            Block::ExtensionBlocks(extensions::ORDERED_LIST, items, attributes, _) => {
+                let start = attribute(&attributes, "start").and_then(|s| s.parse().ok()).unwrap_or(1);
+                let delimiter = match attribute(&attributes, "delimiter") {
+                    Some(")") => ')',
+                    _ => '.',
+                };
+                for (n, item) in (start..).zip(items) {
+                    self.emit_list_item(format!("{}{}", n, delimiter).as_ref(), item, out);
+                }
+            }
+            item @ Block::ExtensionBlock(extensions::LIST_ITEM, ..)
+            | item @ Block::ExtensionBlocks(extensions::LIST_ITEM, ..) => {
+                self.emit_list_item("-", item, out);
+            }
+            Block::ExtensionBlock(_, e, _) => self.emit_paragraph(e, out),
+            Block::ExtensionBlocks(_, blocks, _, _) => {
+                let mut inner = String::new();
+                for block in blocks {
+                    self.emit_block(block, &mut inner);
+                }
+                out.push_str(inner.as_ref());
+            }
+        }
+    }
+
+    fn emit_text(&self, text: &str, out: &mut String) {
+        for c in text.chars() {
+            if let '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '&' | '~' | '|' = c {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+    }
+
+    fn emit_extension_element(&self, tag: ExtensionTag, elements: Vec<Element>, out: &mut String) {
+        let (prefix, suffix) = match tag {
+            tag if self.formats.element_map.contains_key(&tag) => {
+                let format = self.formats.element_map[&tag](&elements);
+                let mut inner = String::new();
+                self.emit_elements(elements, &mut inner);
+                out.push_str(format.apply(inner).as_ref());
+                return;
+            }
+            extensions::BOLD => ("**", "**"),
+            extensions::ITALICS => ("*", "*"),
+            _ => ("", ""),
+        };
+        out.push_str(prefix);
+        self.emit_elements(elements, out);
+        out.push_str(suffix);
+    }
+}
@@ -0,0 +1,430 @@
+use super::*;
+
+/// Prints blocks back as Publication source. Extensions get to print their
+/// own blocks and elements through `Extension::print_block` and
+/// `Extension::print_element`, so the printer should be given the same
+/// extensions as the parser that produced the blocks.
+///
+/// Whitespace is not kept, so the output is a normalized form of the source,
+/// but parsing it again gives back the same document.
+#[derive(Clone)]
+pub struct PublicationEmitter {
+    extensions: Extensions,
+    width: Option<usize>,
+    indent: usize,
+    comment_syntax: CommentSyntax,
+}
+
+/// Source printed by a `PublicationEmitter`, along with what the printer has
+/// to know about it: the comments of the document being printed, and where
+/// the paragraph being printed to it may be wrapped.
+pub struct Printout<'t> {
+    text: String,
+    trivia: Option<&'t Trivia>,
+    // The offsets of the spaces in text where a paragraph may be wrapped.
+    // Spaces that extensions print themselves, or that are printed to some
+    // other printout and then copied in, are never wrapped at.
+    breaks: Option<Vec<usize>>,
+}
+
+impl Printout<'static> {
+    pub fn new() -> Printout<'static> {
+        Printout {
+            text: String::new(),
+            trivia: None,
+            breaks: None,
+        }
+    }
+}
+
+impl Default for Printout<'static> {
+    fn default() -> Printout<'static> {
+        Printout::new()
+    }
+}
+
+impl<'t> Printout<'t> {
+    /// A printout of a document with the comments of `trivia`.
+    pub fn with_trivia(trivia: &'t Trivia) -> Printout<'t> {
+        Printout {
+            text: String::new(),
+            trivia: Some(trivia),
+            breaks: None,
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.text.push(c);
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+
+    // An empty printout of the same document, for text that is copied into
+    // this one once it's printed.
+    fn nested(&self) -> Printout<'t> {
+        Printout {
+            text: String::new(),
+            trivia: self.trivia,
+            breaks: None,
+        }
+    }
+
+    fn trivia_of(&self, block: &Block) -> Option<&'t BlockTrivia> {
+        self.trivia.and_then(|trivia| trivia.of(block))
+    }
+}
+
+impl PublicationEmitter {
+    pub fn new() -> PublicationEmitter {
+        PublicationEmitter::with_extensions(Extensions::default())
+    }
+
+    pub(crate) fn with_extensions(extensions: Extensions) -> PublicationEmitter {
+        PublicationEmitter {
+            extensions,
+            width: None,
+            indent: 0,
+            comment_syntax: CommentSyntax::default(),
+        }
+    }
+
+    pub fn add_extension<E: Extension + 'static>(&mut self, extension: E) {
+        Arc::make_mut(&mut self.extensions).push(Arc::new(extension));
+    }
+
+    /// Wraps paragraphs so that lines are no wider than `width` columns,
+    /// unless a single word is wider than that.
+    pub fn wrap_at(&mut self, width: usize) {
+        self.width = Some(width);
+    }
+
+    /// The comment syntax of the parser that will read the output, so that
+    /// text that looks like a comment can be escaped.
+    pub fn comment_syntax(&mut self, syntax: CommentSyntax) {
+        self.comment_syntax = syntax;
+    }
+
+    // A printer for content that is indented `indent` more columns.
+    fn indented(&self, indent: usize) -> PublicationEmitter {
+        PublicationEmitter {
+            indent: self.indent + indent,
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for PublicationEmitter {
+    fn default() -> PublicationEmitter {
+        PublicationEmitter::new()
+    }
+}
+
+impl PublicationEmitter {
+    /// Prints a whole document along with the comments of `trivia`, as
+    /// returned by `Parser::parse_lossless`. Trailing comments stay at the end
+    /// of the line, but since paragraphs are rewrapped, the other comments of
+    /// a block are put on lines of their own before it.
+    pub fn print_document(&self, blocks: &[Block], trivia: &Trivia) -> String {
+        let mut out = Printout::with_trivia(trivia);
+        self.print_blocks(blocks, &mut out);
+        let mut out = out.into_string();
+        if !trivia.end.is_empty() && !out.is_empty() {
+            out.push_str("\n\n");
+        }
+        for comment in &trivia.end {
+            out.push_str(comment.text.as_ref());
+            out.push('\n');
+        }
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Prints top-level blocks, separated by blank lines.
+    pub fn print_blocks(&self, blocks: &[Block], out: &mut Printout) {
+        for (i, block) in blocks.iter().enumerate() {
+            if i > 0 {
+                out.push_str("\n\n");
+            }
+            self.print_block(block, out);
+        }
+    }
+
+    /// Prints a block without a trailing newline.
+    pub fn print_block(&self, block: &Block, out: &mut Printout) {
+        self.print_leading_comments(block, out);
+        self.print_block_without_leading_comments(block, out);
+    }
+
+    fn print_block_without_leading_comments(&self, block: &Block, out: &mut Printout) {
+        let mut printed = out.nested();
+        let printed_by_extension = self.extensions.iter().any(|ext| ext.print_block(self, block, &mut printed));
+        if !printed_by_extension {
+            match block {
+                Block::Paragraph(elements, _) => {
+                    if self.width.is_some() {
+                        printed.breaks = Some(vec![]);
+                    }
+                    self.print_elements(elements, &mut printed);
+                    if self.escape_block_start(&mut printed.text) {
+                        printed.breaks.iter_mut().flatten().for_each(|offset| *offset += 1);
+                    }
+                }
+                Block::ExtensionBlock(_, elements, _) => self.print_elements(elements, &mut printed),
+                Block::ExtensionBlocks(_, blocks, _, _) => self.print_blocks(blocks, &mut printed),
+            }
+        }
+
+        match (self.width, printed.breaks.as_ref()) {
+            (Some(width), Some(breaks)) if !breaks.is_empty() => {
+                let width = width.saturating_sub(self.indent).max(1);
+                out.push_str(self.wrap(printed.as_str(), breaks, width).as_ref());
+            }
+            _ => out.push_str(printed.as_str()),
+        }
+
+        let trailing = out.trivia_of(block).map(|trivia| trivia.trailing.as_slice()).unwrap_or_default();
+        for comment in trailing {
+            out.push(' ');
+            // The lines of a block comment after the first are kept relative
+            // to the column it starts at.
+            let column = out.text.rsplit('\n').next().unwrap_or_default().chars().count();
+            for (i, line) in comment.text.split('\n').enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                    if !line.is_empty() {
+                        out.push_str(&" ".repeat(column));
+                    }
+                }
+                out.push_str(line);
+            }
+        }
+    }
+
+    pub fn print_elements(&self, elements: &[Element], out: &mut Printout) {
+        for element in elements {
+            self.print_element(element, out);
+        }
+    }
+
+    /// Prints an element. The spaces of text printed here may be turned into
+    /// line breaks if `out` is a paragraph that is being wrapped.
+    pub fn print_element(&self, element: &Element, out: &mut Printout) {
+        for ext in self.extensions.iter() {
+            if ext.print_element(self, element, out) {
+                return;
+            }
+        }
+        match element {
+            Element::Text(text, _) => self.print_text(text, out),
+            Element::ExtensionElement(_, elements, _) => self.print_elements(elements, out),
+        }
+    }
+
+    /// Prints text, escaping comments, backslashes and the special characters
+    /// of the extensions.
+    pub fn print_text(&self, text: &str, out: &mut Printout) {
+        let special: Vec<char> = self.extensions.iter().flat_map(|ext| ext.special_characters()).collect();
+        let mut chars = text.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            let escape = match c {
+                '\\' => chars
+                    .peek()
+                    .map(|&(_, next)| !next.is_alphanumeric() && !next.is_whitespace())
+                    .unwrap_or(true),
+                c => special.contains(&c) || self.comment_syntax.starts_comment(&text[i..]),
+            };
+            if escape {
+                out.push('\\');
+            }
+            if let (' ', Some(breaks)) = (c, out.breaks.as_mut()) {
+                breaks.push(out.text.len());
+            }
+            out.push(c);
+        }
+    }
+
+    /// Prints the blocks of a list item after `marker`, indenting every line
+    /// after the first so that it stays inside the item.
+    pub fn print_list_item(&self, marker: &str, item: &Block, out: &mut Printout) {
+        let indent = marker.chars().count() + 1;
+        let mut inner = out.nested();
+        let printer = self.indented(indent);
+        match item {
+            Block::ExtensionBlocks(_, blocks, _, _) => {
+                match blocks.first() {
+                    // Comments in the first paragraph go before the marker.
+                    Some(first @ Block::Paragraph(..)) => self.print_leading_comments(first, out),
+                    // Anything but a paragraph has to start on the line after the marker.
+                    Some(_) => inner.push('\n'),
+                    None => {}
+                }
+                for (i, block) in blocks.iter().enumerate() {
+                    let mut printed = out.nested();
+                    match (i, block) {
+                        (0, Block::Paragraph(..)) => printer.print_block_without_leading_comments(block, &mut printed),
+                        _ => printer.print_block(block, &mut printed),
+                    }
+                    match (i.checked_sub(1).map(|i| &blocks[i]), block) {
+                        (None, _) => {}
+                        // Sub-lists follow the paragraph before them without a
+                        // blank line if they would end it, but not another
+                        // list, which they would join.
+                        (Some(Block::Paragraph(..)), Block::ExtensionBlocks(extensions::LIST, ..))
+                        | (Some(Block::Paragraph(..)), Block::ExtensionBlocks(extensions::ORDERED_LIST, ..))
+                            if self.ends_paragraph(printed.as_str()) =>
+                        {
+                            inner.push('\n')
+                        }
+                        _ => inner.push_str("\n\n"),
+                    }
+                    inner.push_str(printed.as_str());
+                }
+            }
+            block => {
+                self.print_leading_comments(block, out);
+                printer.print_block_without_leading_comments(block, &mut inner);
+            }
+        }
+
+        let indent = " ".repeat(indent);
+        out.push_str(marker);
+        for (i, line) in inner.as_str().split('\n').enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            if line.is_empty() {
+                continue;
+            }
+            out.push_str(if i == 0 { " " } else { indent.as_ref() });
+            out.push_str(line);
+        }
+    }
+
+    fn print_leading_comments(&self, block: &Block, out: &mut Printout) {
+        let trivia = match out.trivia_of(block) {
+            Some(trivia) => trivia,
+            None => return,
+        };
+        for comment in trivia.leading.iter().chain(&trivia.inline) {
+            out.push_str(comment.text.as_ref());
+            out.push('\n');
+        }
+    }
+
+    // Whether `text` would end a paragraph if it followed it on the next line.
+    fn ends_paragraph(&self, text: &str) -> bool {
+        let mut parser = Parser::new(text);
+        parser.extensions = self.extensions.clone();
+        parser.comment_syntax = self.comment_syntax.clone();
+        parser.move_past_whitespace();
+        parser.sees_end_of_block()
+    }
+
+    /// Fills lines with as many words as fit, breaking only at the spaces at
+    /// `breaks`. A word is never put first on a line if the parser would take
+    /// it to start a new block there.
+    fn wrap(&self, text: &str, breaks: &[usize], width: usize) -> String {
+        let mut words = vec![];
+        let mut from = 0;
+        for &offset in breaks {
+            words.push(&text[from..offset]);
+            from = offset + 1;
+        }
+        words.push(&text[from..]);
+        words.retain(|word| !word.is_empty());
+
+        let mut out = String::new();
+        let mut line_width = 0;
+        for (j, word) in words.iter().enumerate() {
+            let first_line = word.split('\n').next().unwrap_or_default();
+            if j > 0 {
+                if line_width + 1 + first_line.width() > width && !self.starts_block(&words[j..]) {
+                    out.push('\n');
+                    line_width = 0;
+                } else {
+                    out.push(' ');
+                    line_width += 1;
+                }
+            }
+            out.push_str(word);
+            line_width = match word.rfind('\n') {
+                Some(i) => word[i + 1..].width(),
+                None => line_width + word.width(),
+            };
+        }
+        out
+    }
+
+    /// Escapes the first character of the first word of a paragraph that
+    /// would otherwise be read as the start of some other block, like a list,
+    /// or as a comment. Text that looks like a comment is escaped already, but
+    /// the delimiters of elements next to each other, like those of an empty
+    /// one, can still make up a comment marker, which the parser only gives
+    /// way to elements for within a paragraph. Returns whether it did.
+    fn escape_block_start(&self, paragraph: &mut String) -> bool {
+        let words: Vec<&str> = paragraph.split(' ').collect();
+        if !self.comment_syntax.starts_comment(paragraph) && !self.starts_block(&words) {
+            return false;
+        }
+        let first_word = paragraph.find(' ').unwrap_or(paragraph.len());
+        match paragraph[..first_word].find(|c: char| !c.is_alphanumeric()) {
+            Some(i) => {
+                paragraph.insert(i, '\\');
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn starts_block(&self, words: &[&str]) -> bool {
+        // Blocks can only start where an extension is triggered, or at the
+        // characters that end a block by themselves.
+        match words.first().and_then(|word| word.chars().next()) {
+            Some(c) if self.extensions.triggered_by(c).is_empty() && c != '\n' && c != '\0' => return false,
+            _ => {}
+        }
+        // A marker is followed by the start of its block, so a few words of
+        // it are enough to go on.
+        let text = words[..words.len().min(3)].join(" ");
+        let mut parser = Parser::new(&text);
+        parser.extensions = self.extensions.clone();
+        // Some blocks only interrupt a paragraph in places, like the next item
+        // of a list that it's in, so anything that would start a block after a
+        // blank line is kept off the start of a line too, as are markers like
+        // `aa.` that could only be the next item of an alphabetic list.
+        for numbering in [extensions::Numbering::LowerAlpha, extensions::Numbering::UpperAlpha] {
+            parser.ordered_lists.extend([(numbering, '.'), (numbering, ')')]);
+        }
+        parser.sees_end_of_block() || !matches!(parser.parse_block(), Ok(Block::Paragraph(..)))
+    }
+}
+
+impl Emitter for PublicationEmitter {
+    fn emit_block(&self, block: Block, out: &mut String) {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        let mut printed = Printout::new();
+        self.print_block(&block, &mut printed);
+        out.push_str(printed.as_str());
+        out.push('\n');
+    }
+
+    fn emit_element(&self, element: Element, out: &mut String) {
+        let mut printed = Printout::new();
+        self.print_element(&element, &mut printed);
+        out.push_str(printed.as_str());
+    }
+}
@@ -0,0 +1,81 @@
+use super::*;
+use std::error::Error;
+use std::{fmt, io};
+
+/// Emits blocks one at a time, so that output can be written out as the
+/// blocks are parsed instead of being built up in memory.
+pub struct StreamingEmitter<'e> {
+    emitter: &'e dyn Emitter,
+    buffer: String,
+    emitted_any: bool,
+}
+
+impl<'e> StreamingEmitter<'e> {
+    pub fn new(emitter: &'e dyn Emitter) -> StreamingEmitter<'e> {
+        StreamingEmitter {
+            emitter,
+            buffer: String::new(),
+            emitted_any: false,
+        }
+    }
+
+    /// Emits `block`, returning the text to append to the output so far.
+    pub fn emit(&mut self, block: Block) -> &str {
+        // Emitters separate a block from the one before it if the output is
+        // not empty, so the buffer is made to look like it continues it.
+        self.buffer.clear();
+        if self.emitted_any {
+            self.buffer.push('\n');
+        }
+        let start = self.buffer.len();
+        self.emitter.emit_block(block, &mut self.buffer);
+        self.emitted_any |= self.buffer.len() > start;
+        &self.buffer[start..]
+    }
+}
+
+/// Why emitting to a writer failed.
+#[derive(Debug)]
+pub enum EmitError {
+    Parse(Box<Diagnostic>),
+    Io(io::Error),
+    Fmt(fmt::Error),
+}
+
+impl fmt::Display for EmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmitError::Parse(diagnostic) => fmt::Display::fmt(diagnostic, f),
+            EmitError::Io(error) => fmt::Display::fmt(error, f),
+            EmitError::Fmt(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+impl Error for EmitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EmitError::Parse(diagnostic) => Some(diagnostic.as_ref()),
+            EmitError::Io(error) => Some(error),
+            EmitError::Fmt(error) => Some(error),
+        }
+    }
+}
+
+impl From<Box<Diagnostic>> for EmitError {
+    fn from(diagnostic: Box<Diagnostic>) -> EmitError {
+        EmitError::Parse(diagnostic)
+    }
+}
+
+impl From<io::Error> for EmitError {
+    fn from(error: io::Error) -> EmitError {
+        EmitError::Io(error)
+    }
+}
+
+impl From<fmt::Error> for EmitError {
+    fn from(error: fmt::Error) -> EmitError {
+        EmitError::Fmt(error)
+    }
+}
@@ -1,103 +1,230 @@
 use crate::*;
+use std::collections::HashMap;
 
-pub trait Extension {
-    fn parse_block(&self, _parser: &mut Parser) -> ParseResult<Option<Block>> {
+/// Extensions are shared between parsers, possibly on different threads.
+pub trait Extension: Send + Sync {
+    fn parse_block<'a>(&self, _parser: &mut Parser<'a>) -> ParseResult<Option<Block<'a>>> {
         Ok(None)
     }
 
-    fn parse_element(&self, _parser: &mut Parser) -> ParseResult<Option<Element>> {
+    fn parse_element<'a>(&self, _parser: &mut Parser<'a>) -> ParseResult<Option<Element<'a>>> {
         Ok(None)
     }
 
     fn sees_end_of_block(&self, _parser: &Parser) -> bool {
         false
     }
+
+    /// The characters that this extension's blocks and elements, and the
+    /// block boundaries it sees, can start with. The parser only tries the
+    /// extension where one of them is next, so that extensions which declare
+    /// them cost nothing elsewhere. `None` means it is tried everywhere.
+    fn trigger_characters(&self) -> Option<Vec<char>> {
+        None
+    }
+
+    /// Characters that would be read as this extension's syntax anywhere in a
+    /// paragraph. The `PublicationEmitter` escapes them in text.
+    fn special_characters(&self) -> Vec<char> {
+        vec![]
+    }
+
+    /// Prints `block` back in the syntax of this extension, returning `false`
+    /// if it's not one of the extension's blocks.
+    fn print_block(&self, _printer: &PublicationEmitter, _block: &Block, _out: &mut Printout) -> bool {
+        false
+    }
+
+    /// Prints `element` back in the syntax of this extension, returning
+    /// `false` if it's not one of the extension's elements.
+    fn print_element(&self, _printer: &PublicationEmitter, _element: &Element, _out: &mut Printout) -> bool {
+        false
+    }
 }
 
-pub const BOLD: ExtensionTag = ExtensionTag("builtin:BOLD");
+/// The extensions of a parser, indexed by the characters that trigger them.
+#[derive(Clone, Default)]
+pub(crate) struct ExtensionSet {
+    all: Vec<Arc<dyn Extension>>,
+    // The extensions to try before each ASCII character, or none at all if
+    // there are no extensions.
+    ascii: Vec<Vec<Arc<dyn Extension>>>,
+    other: HashMap<char, Vec<Arc<dyn Extension>>>,
+    untriggered: Vec<Arc<dyn Extension>>,
+}
+
+impl ExtensionSet {
+    pub(crate) fn push(&mut self, extension: Arc<dyn Extension>) {
+        self.all.push(extension);
+
+        let triggers: Vec<Option<Vec<char>>> = self.all.iter().map(|ext| ext.trigger_characters()).collect();
+        let triggered_by = |c: char| -> Vec<Arc<dyn Extension>> {
+            self.all
+                .iter()
+                .zip(triggers.iter())
+                .filter(|(_, triggers)| triggers.as_ref().map(|t| t.contains(&c)).unwrap_or(true))
+                .map(|(ext, _)| ext.clone())
+                .collect()
+        };
+        let ascii = (0..128u8).map(|c| triggered_by(c as char)).collect();
+        let other = triggers
+            .iter()
+            .flatten()
+            .flatten()
+            .filter(|c| !c.is_ascii())
+            .map(|&c| (c, triggered_by(c)))
+            .collect();
+        let untriggered = self.all.iter().zip(triggers.iter()).filter(|(_, t)| t.is_none()).map(|(ext, _)| ext.clone());
+
+        self.untriggered = untriggered.collect();
+        self.ascii = ascii;
+        self.other = other;
+    }
+
+    pub(crate) fn iter(&self) -> std::slice::Iter<'_, Arc<dyn Extension>> {
+        self.all.iter()
+    }
+
+    /// The extensions to try when `c` is next, in the order they were added.
+    #[inline]
+    pub(crate) fn triggered_by(&self, c: char) -> &[Arc<dyn Extension>] {
+        if c.is_ascii() {
+            self.ascii.get(c as usize).unwrap_or(&self.untriggered)
+        } else {
+            self.other.get(&c).unwrap_or(&self.untriggered)
+        }
+    }
+}
+
+pub const BOLD: ExtensionTag = ExtensionTag::from_static("builtin:BOLD");
+pub const UNCLOSED_BOLD: DiagnosticCode = DiagnosticCode::from_static("builtin:UNCLOSED_BOLD");
 
 pub struct Bold;
 
 impl Extension for Bold {
-    fn parse_element(&self, parser: &mut Parser) -> ParseResult<Option<Element>> {
-        if parser.peek() != '*' {
-            return Ok(None);
-        }
+    fn parse_element<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Element<'a>>> {
+        parse_delimited(parser, '*', BOLD, UNCLOSED_BOLD, "bold")
+    }
 
-        parser.take(); // *
+    fn trigger_characters(&self) -> Option<Vec<char>> {
+        Some(vec!['*'])
+    }
 
-        let mut text = String::new();
-        loop {
-            if parser.sees_end_of_block() {
-                return Ok(None);
-            }
-            match parser.take() {
-                '*' => return Ok(Some(Element::ExtensionElement(BOLD, Box::new(Element::Text(text))))),
+    fn special_characters(&self) -> Vec<char> {
+        vec!['*']
+    }
 
-                c => text.push(c),
-            }
-        }
+    fn print_element(&self, printer: &PublicationEmitter, element: &Element, out: &mut Printout) -> bool {
+        print_delimited(printer, element, '*', BOLD, out)
     }
 }
 
-pub const ITALICS: ExtensionTag = ExtensionTag("builtin:ITALICS");
+pub const ITALICS: ExtensionTag = ExtensionTag::from_static("builtin:ITALICS");
+pub const UNCLOSED_ITALICS: DiagnosticCode = DiagnosticCode::from_static("builtin:UNCLOSED_ITALICS");
 
 pub struct Italics;
 
 impl Extension for Italics {
-    fn parse_element(&self, parser: &mut Parser) -> ParseResult<Option<Element>> {
-        if parser.peek() != '/' {
-            return Ok(None);
-        }
+    fn parse_element<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Element<'a>>> {
+        parse_delimited(parser, '/', ITALICS, UNCLOSED_ITALICS, "italic")
+    }
 
-        parser.take(); // /
+    fn trigger_characters(&self) -> Option<Vec<char>> {
+        Some(vec!['/'])
+    }
 
-        let mut text = String::new();
-        loop {
-            if parser.sees_end_of_block() {
-                return Ok(None);
-            }
-            match parser.take() {
-                '/' => return Ok(Some(Element::ExtensionElement(ITALICS, Box::new(Element::Text(text))))),
+    fn special_characters(&self) -> Vec<char> {
+        vec!['/']
+    }
 
-                c => text.push(c),
-            }
+    fn print_element(&self, printer: &PublicationEmitter, element: &Element, out: &mut Printout) -> bool {
+        print_delimited(printer, element, '/', ITALICS, out)
+    }
+}
+
+fn parse_delimited<'a>(
+    parser: &mut Parser<'a>,
+    delimiter: char,
+    tag: ExtensionTag,
+    unclosed: DiagnosticCode,
+    description: &str,
+) -> ParseResult<Option<Element<'a>>> {
+    if parser.peek() != delimiter {
+        return Ok(None);
+    }
+
+    let start = parser.position();
+    parser.take(); // delimiter
+    let delimiter_span = parser.span_from(start);
+
+    match parser.parse_elements_until(delimiter.encode_utf8(&mut [0; 4]))? {
+        Some(children) => Ok(Some(Element::ExtensionElement(tag, children, parser.span_from(start)))),
+        None => {
+            parser.report(
+                Diagnostic::warning(delimiter_span, format!("unclosed `{}`", delimiter))
+                    .with_code(unclosed)
+                    .with_expected(format!("`{}`", delimiter))
+                    .with_help(format!(
+                        "add a closing `{}` after the {} text, or remove this one",
+                        delimiter, description
+                    )),
+            )?;
+            Ok(None)
         }
     }
 }
 
-pub const LIST: ExtensionTag = ExtensionTag("builtin:LIST");
-pub const LIST_ITEM: ExtensionTag = ExtensionTag("builtin:LIST_ITEM");
+fn print_delimited(
+    printer: &PublicationEmitter,
+    element: &Element,
+    delimiter: char,
+    tag: ExtensionTag,
+    out: &mut Printout,
+) -> bool {
+    match element {
+        Element::ExtensionElement(t, elements, _) if *t == tag => {
+            out.push(delimiter);
+            printer.print_elements(elements, out);
+            out.push(delimiter);
+            true
+        }
+        _ => false,
+    }
+}
 
-pub struct Lists(Vec<char>);
+pub const LIST: ExtensionTag = ExtensionTag::from_static("builtin:LIST");
+pub const LIST_ITEM: ExtensionTag = ExtensionTag::from_static("builtin:LIST_ITEM");
+
+pub struct Lists(String);
 
 impl Lists {
     pub fn new<B: AsRef<str>>(bullet: B) -> Lists {
-        Lists(bullet.as_ref().chars().collect())
+        Lists(bullet.as_ref().into())
     }
 
     fn sees_bullet(&self, parser: &Parser) -> bool {
-        let bullet_chars = self.0.as_slice();
-        parser.peek_many(bullet_chars.len()) == bullet_chars
+        parser.rest_bytes().starts_with(self.0.as_bytes()) && parser.is_at_start_of_line()
     }
 
-    fn parse_list(&self, parser: &mut Parser) -> ParseResult<Block> {
+    fn parse_list<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Block<'a>> {
+        let start = parser.position();
         let mut items = vec![];
-        while self.sees_bullet(parser) {
+        while self.sees_bullet(parser) && parser.position().column == start.column {
             items.push(self.parse_list_item(parser)?);
         }
-        Ok(Block::ExtensionBlocks(LIST, items))
+        Ok(Block::ExtensionBlocks(LIST, items, vec![], parser.span_from(start)))
     }
 
-    fn parse_list_item(&self, parser: &mut Parser) -> ParseResult<Block> {
-        parser.take_many(self.0.len()); // take bullet
-        parser.move_past_whitespace();
-        Ok(Block::ExtensionBlock(LIST_ITEM, parser.parse_elements()?))
+    fn parse_list_item<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Block<'a>> {
+        let start = parser.position();
+        parser.take_many(self.0.chars().count()); // take bullet
+        let blocks = parse_list_item_blocks(parser, start)?;
+        Ok(Block::ExtensionBlocks(LIST_ITEM, blocks, vec![], parser.span_from(start)))
     }
 }
 
 impl Extension for Lists {
-    fn parse_block(&self, parser: &mut Parser) -> ParseResult<Option<Block>> {
+    fn parse_block<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Block<'a>>> {
         Ok(if self.sees_bullet(parser) {
             Some(self.parse_list(parser)?)
         } else {
@@ -108,6 +235,324 @@ impl Extension for Lists {
     fn sees_end_of_block(&self, parser: &Parser) -> bool {
         self.sees_bullet(parser)
     }
+
+    fn trigger_characters(&self) -> Option<Vec<char>> {
+        self.0.chars().next().map(|c| vec![c])
+    }
+
+    fn print_block(&self, printer: &PublicationEmitter, block: &Block, out: &mut Printout) -> bool {
+        match block {
+            Block::ExtensionBlocks(LIST, items, _, _) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push('\n');
+                    }
+                    printer.print_list_item(self.0.as_ref(), item, out);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parses the content of a list item whose marker started at `start` and has
+/// just been consumed. Anything indented past the marker, including sub-lists
+/// and further paragraphs after blank lines, belongs to the item. Only text
+/// on the line of the marker starts a paragraph right away; content that
+/// starts on a later line is parsed as a block of its own.
+fn parse_list_item_blocks<'a>(parser: &mut Parser<'a>, start: Position) -> ParseResult<Vec<Block<'a>>> {
+    // Like `move_past_whitespace`, except that a blank line after an empty
+    // item ends it, so that it still separates the list from the next one.
+    while !parser.sees_end_of_block() {
+        match parser.peek() {
+            _ if parser.sees_comment() => parser.move_past_comment(),
+            w if w.is_whitespace() => parser.advance(),
+            _ => break,
+        }
+    }
+
+    let mut blocks = vec![];
+    let content_start = parser.position();
+    if content_start.line == start.line {
+        let elements = parser.parse_elements()?;
+        if !elements.is_empty() {
+            blocks.push(Block::Paragraph(elements, parser.span_from(content_start)));
+        }
+    }
+
+    loop {
+        let checkpoint = parser.checkpoint();
+        parser.move_past_whitespace();
+        if parser.is_at_end() || parser.position().column <= start.column {
+            parser.restore(checkpoint);
+            break;
+        }
+        blocks.push(parser.parse_block()?);
+    }
+
+    Ok(blocks)
+}
+
+pub const ORDERED_LIST: ExtensionTag = ExtensionTag::from_static("builtin:ORDERED_LIST");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Numbering {
+    Decimal,
+    LowerAlpha,
+    UpperAlpha,
+    LowerRoman,
+    UpperRoman,
+}
+
+impl Numbering {
+    /// The value of the `type` attribute of an HTML `<ol>` with this numbering.
+    pub fn html_type(self) -> &'static str {
+        match self {
+            Numbering::Decimal => "1",
+            Numbering::LowerAlpha => "a",
+            Numbering::UpperAlpha => "A",
+            Numbering::LowerRoman => "i",
+            Numbering::UpperRoman => "I",
+        }
+    }
+
+    pub fn from_html_type(html_type: &str) -> Option<Numbering> {
+        match html_type {
+            "1" => Some(Numbering::Decimal),
+            "a" => Some(Numbering::LowerAlpha),
+            "A" => Some(Numbering::UpperAlpha),
+            "i" => Some(Numbering::LowerRoman),
+            "I" => Some(Numbering::UpperRoman),
+            _ => None,
+        }
+    }
+
+    pub fn format(self, number: usize) -> String {
+        match self {
+            Numbering::Decimal => number.to_string(),
+            Numbering::LowerAlpha => alpha(number),
+            Numbering::UpperAlpha => alpha(number).to_uppercase(),
+            Numbering::LowerRoman => roman(number),
+            Numbering::UpperRoman => roman(number).to_uppercase(),
+        }
+    }
+
+    fn parse(self, marker: &str) -> Option<usize> {
+        let is_lower = marker.chars().all(|c| c.is_ascii_lowercase());
+        let is_upper = marker.chars().all(|c| c.is_ascii_uppercase());
+        match self {
+            Numbering::Decimal => marker.parse().ok(),
+            Numbering::LowerAlpha if is_lower => parse_alpha(marker),
+            Numbering::UpperAlpha if is_upper => parse_alpha(marker),
+            Numbering::LowerRoman if is_lower => parse_roman(marker),
+            Numbering::UpperRoman if is_upper => parse_roman(marker),
+            _ => None,
+        }
+    }
+
+    /// Guesses the numbering of the first item of a list. A lone `i` or `I`
+    /// is taken to be roman, but any other single letter is alphabetic.
+    fn detect(marker: &str) -> Option<Numbering> {
+        let guesses = if marker.len() == 1 && !"iI".contains(marker) {
+            [Numbering::Decimal, Numbering::LowerAlpha, Numbering::UpperAlpha]
+        } else {
+            [Numbering::Decimal, Numbering::LowerRoman, Numbering::UpperRoman]
+        };
+        guesses.iter().cloned().find(|n| n.parse(marker).is_some())
+    }
+}
+
+fn alpha(mut number: usize) -> String {
+    let mut out = vec![];
+    while number > 0 {
+        number -= 1;
+        out.push((b'a' + (number % 26) as u8) as char);
+        number /= 26;
+    }
+    out.iter().rev().collect()
+}
+
+/// Reads markers written by `alpha`, so that lists past `z` continue with
+/// `aa`, `ab` and so on.
+fn parse_alpha(marker: &str) -> Option<usize> {
+    marker.to_ascii_lowercase().bytes().try_fold(0usize, |number, b| {
+        number.checked_mul(26)?.checked_add((b - b'a') as usize + 1)
+    })
+}
+
+const ROMAN: [(usize, &str); 13] = [
+    (1000, "m"),
+    (900, "cm"),
+    (500, "d"),
+    (400, "cd"),
+    (100, "c"),
+    (90, "xc"),
+    (50, "l"),
+    (40, "xl"),
+    (10, "x"),
+    (9, "ix"),
+    (5, "v"),
+    (4, "iv"),
+    (1, "i"),
+];
+
+fn roman(mut number: usize) -> String {
+    let mut out = String::new();
+    for (value, digits) in ROMAN.iter() {
+        while number >= *value {
+            out.push_str(digits);
+            number -= value;
+        }
+    }
+    out
+}
+
+fn parse_roman(marker: &str) -> Option<usize> {
+    let lower = marker.to_lowercase();
+    let mut rest = lower.as_str();
+    let mut number = 0;
+    for (value, digits) in ROMAN.iter() {
+        while rest.starts_with(digits) {
+            rest = &rest[digits.len()..];
+            number += value;
+        }
+    }
+    if rest.is_empty() && number > 0 && roman(number) == lower {
+        Some(number)
+    } else {
+        None
+    }
+}
+
+/// Numbered lists, with markers like `1.`, `1)`, `a.` or `iv)`. The numbering
+/// style and delimiter of the first item are kept for the whole list, and the
+/// first number is kept as the `start` attribute.
+pub struct OrderedLists;
+
+struct Marker {
+    numbering: Numbering,
+    number: usize,
+    delimiter: char,
+    len: usize,
+}
+
+impl OrderedLists {
+    fn sees_marker(&self, parser: &Parser, numbering: Option<Numbering>) -> Option<Marker> {
+        if !parser.peek().is_ascii_alphanumeric() || !parser.is_at_start_of_line() {
+            return None;
+        }
+
+        let text = parser.peek_many(12);
+        let len = text.chars().take_while(|c| c.is_ascii_alphanumeric()).count();
+        let mut after = text[len..].chars();
+        let delimiter = after.next()?;
+        if delimiter != '.' && delimiter != ')' {
+            return None;
+        }
+        match after.next() {
+            Some(c) if c.is_whitespace() => {}
+            // The end of the input, rather than of the peeked text.
+            None if parser.rest_bytes().len() == text.len() => {}
+            _ => return None,
+        }
+
+        let marker = &text[..len];
+        let numbering = numbering.or_else(|| Numbering::detect(marker))?;
+        Some(Marker {
+            numbering,
+            number: numbering.parse(marker)?,
+            delimiter,
+            len: len + 1,
+        })
+    }
+
+    /// The marker of the next item of the list, or of one that it's nested
+    /// in, if one starts here.
+    fn sees_next_item(&self, parser: &Parser) -> Option<Marker> {
+        parser.ordered_lists.iter().find_map(|&(numbering, delimiter)| {
+            self.sees_marker(parser, Some(numbering)).filter(|marker| marker.delimiter == delimiter)
+        })
+    }
+
+    fn parse_list<'a>(&self, parser: &mut Parser<'a>, first: Marker) -> ParseResult<Block<'a>> {
+        let start = parser.position();
+        parser.ordered_lists.push((first.numbering, first.delimiter));
+        let items = self.parse_items(parser, &first, start);
+        parser.ordered_lists.pop();
+        let items = items?;
+        let attributes = vec![
+            ("start".into(), first.number.to_string()),
+            ("type".into(), first.numbering.html_type().into()),
+            ("delimiter".into(), first.delimiter.to_string()),
+        ];
+        Ok(Block::ExtensionBlocks(ORDERED_LIST, items, attributes, parser.span_from(start)))
+    }
+
+    fn parse_items<'a>(&self, parser: &mut Parser<'a>, first: &Marker, start: Position) -> ParseResult<Vec<Block<'a>>> {
+        let mut items = vec![];
+        while let Some(marker) = self.sees_marker(parser, Some(first.numbering)) {
+            if marker.delimiter != first.delimiter || parser.position().column != start.column {
+                break;
+            }
+            let item_start = parser.position();
+            parser.take_many(marker.len);
+            let blocks = parse_list_item_blocks(parser, item_start)?;
+            items.push(Block::ExtensionBlocks(LIST_ITEM, blocks, vec![], parser.span_from(item_start)));
+        }
+        Ok(items)
+    }
+}
+
+impl Extension for OrderedLists {
+    fn parse_block<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Block<'a>>> {
+        // A marker like `aa.` only starts a list where it could continue one,
+        // since it ends any paragraph there.
+        match self.sees_marker(parser, None).or_else(|| self.sees_next_item(parser)) {
+            Some(marker) => Ok(Some(self.parse_list(parser, marker)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Like in CommonMark, only a list that starts at `1`, `a` or `i` can
+    /// interrupt a paragraph, so that a line starting with a year or an
+    /// initial doesn't turn into a list. Items of the lists being parsed can
+    /// still end the paragraph before them.
+    fn sees_end_of_block(&self, parser: &Parser) -> bool {
+        match self.sees_marker(parser, None) {
+            Some(Marker {
+                numbering: Numbering::Decimal | Numbering::LowerAlpha | Numbering::LowerRoman,
+                number: 1,
+                ..
+            }) => true,
+            _ => self.sees_next_item(parser).is_some(),
+        }
+    }
+
+    fn trigger_characters(&self) -> Option<Vec<char>> {
+        Some(('0'..='9').chain('a'..='z').chain('A'..='Z').collect())
+    }
+
+    fn print_block(&self, printer: &PublicationEmitter, block: &Block, out: &mut Printout) -> bool {
+        match block {
+            Block::ExtensionBlocks(ORDERED_LIST, items, attributes, _) => {
+                let start = attribute(attributes, "start").and_then(|s| s.parse().ok()).unwrap_or(1);
+                let numbering = attribute(attributes, "type")
+                    .and_then(Numbering::from_html_type)
+                    .unwrap_or(Numbering::Decimal);
+                let delimiter = attribute(attributes, "delimiter").unwrap_or(".");
+                for (i, (number, item)) in (start..).zip(items).enumerate() {
+                    if i > 0 {
+                        out.push('\n');
+                    }
+                    let marker = format!("{}{}", numbering.format(number), delimiter);
+                    printer.print_list_item(marker.as_ref(), item, out);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -116,27 +561,31 @@ mod tests {
 
     #[test]
     fn fully_external_extension() {
-        const MY_TAG: ExtensionTag = ExtensionTag("MY_TAG");
+        const MY_TAG: ExtensionTag = ExtensionTag::from_static("MY_TAG");
 
         struct MyExtension;
 
         impl Extension for MyExtension {
-            fn parse_element(&self, parser: &mut Parser) -> ParseResult<Option<Element>> {
-                let sign = ['$', '$'];
-                if parser.peek_many(2) != &sign {
+            fn parse_element<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Element<'a>>> {
+                if parser.peek_many(2) != "$$" {
                     return Ok(None);
                 }
+                let start = parser.position();
                 parser.take_many(2);
-                let mut content = String::new();
-                while !parser.is_at_end() && parser.peek_many(2) != &sign {
-                    content.push(parser.take());
+                let content_start = parser.position();
+                while !parser.is_at_end() && parser.peek_many(2) != "$$" {
+                    parser.take();
                 }
+                let content = Element::Text(
+                    parser.source_from(content_start).into(),
+                    parser.span_from(content_start),
+                );
                 parser.take_many(2);
-                Ok(Some(Element::ExtensionElement(MY_TAG, Box::new(Element::Text(content)))))
+                Ok(Some(Element::ExtensionElement(MY_TAG, vec![content], parser.span_from(start))))
             }
         }
 
-        let mut parser = Parser::new("This is $$some syntax$$".into());
+        let mut parser = Parser::new("This is $$some syntax$$");
         parser.add_extension(MyExtension);
 
         let mut emitter = HtmlEmitter::new();
@@ -146,4 +595,196 @@ mod tests {
 
         assert_eq!(output, "<p>\n  This is <span>some syntax</span>\n</p>\n");
     }
+
+    #[test]
+    fn extension_defined_errors() {
+        const NO_SHOUTING: DiagnosticCode = DiagnosticCode::from_static("MY_NO_SHOUTING");
+
+        struct NoShouting;
+
+        impl Extension for NoShouting {
+            fn parse_element<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Element<'a>>> {
+                if parser.peek() != '!' {
+                    return Ok(None);
+                }
+                let start = parser.position();
+                parser.take();
+                Err(Box::new(
+                    Diagnostic::error(parser.span_from(start), "exclamation marks are not allowed")
+                        .with_code(NO_SHOUTING),
+                ))
+            }
+        }
+
+        let mut parser = Parser::new("Calm.\n\nNot calm!");
+        parser.add_extension(NoShouting);
+
+        let error = parser.parse().unwrap_err();
+
+        assert_eq!(error.code, Some(NO_SHOUTING));
+        assert_eq!(
+            error.span,
+            Span::new(Position::new(15, 3, 9), Position::new(16, 3, 10))
+        );
+    }
+
+    #[test]
+    fn fully_external_extension_as_text() {
+        const SHOUT: ExtensionTag = ExtensionTag::from_static("MY_SHOUT");
+        const ASIDE: ExtensionTag = ExtensionTag::from_static("MY_ASIDE");
+
+        struct MyExtension;
+
+        impl Extension for MyExtension {
+            fn parse_block<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Block<'a>>> {
+                if parser.peek_many(2) != "> " {
+                    return Ok(None);
+                }
+                let start = parser.position();
+                parser.take_many(2);
+                let paragraph = parser.parse_paragraph_block()?;
+                Ok(Some(Block::ExtensionBlocks(ASIDE, vec![paragraph], vec![], parser.span_from(start))))
+            }
+
+            fn parse_element<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Element<'a>>> {
+                if parser.peek() != '!' {
+                    return Ok(None);
+                }
+                let start = parser.position();
+                parser.take();
+                Ok(parser
+                    .parse_elements_until("!")?
+                    .map(|children| Element::ExtensionElement(SHOUT, children, parser.span_from(start))))
+            }
+        }
+
+        let mut parser = Parser::new("Say !hello! to\n\n> an aside\n  over two lines");
+        parser.add_extension(MyExtension);
+
+        let mut emitter = TextEmitter::new();
+        emitter.tagged_element(SHOUT, |_| {
+            TextFormat::new().with_suffix("!").with_transform(|s| s.to_uppercase())
+        });
+        emitter.tagged_blocks(ASIDE, |_| {
+            TextFormat::new().with_prefix("(").with_suffix(")").with_indent("    ")
+        });
+
+        let output = parser.emit_with(&emitter).unwrap();
+
+        assert_eq!(output, "Say HELLO! to\n\n    (an aside over two lines)\n");
+    }
+
+    #[test]
+    fn end_of_file_says_what_was_expected() {
+        const ASIDE: ExtensionTag = ExtensionTag::from_static("MY_ASIDE");
+
+        struct Aside;
+
+        impl Extension for Aside {
+            fn parse_block<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Block<'a>>> {
+                if parser.peek_many(2) != "> " {
+                    return Ok(None);
+                }
+                let start = parser.position();
+                parser.take_many(2);
+                let paragraph = parser.parse_paragraph_block()?;
+                Ok(Some(Block::ExtensionBlocks(ASIDE, vec![paragraph], vec![], parser.span_from(start))))
+            }
+        }
+
+        let mut parser = Parser::new("An aside that never starts:\n\n> ");
+        parser.add_extension(Aside);
+
+        let error = parser.parse().unwrap_err();
+
+        assert_eq!(error.code, Some(UNEXPECTED_END_OF_FILE));
+        assert_eq!(error.expected, vec!["a paragraph"]);
+    }
+
+    #[test]
+    fn extensions_are_only_tried_at_their_trigger_characters() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        const MENTION: ExtensionTag = ExtensionTag::from_static("MY_MENTION");
+
+        struct Mentions(Arc<AtomicUsize>);
+
+        impl Extension for Mentions {
+            fn parse_element<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Element<'a>>> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                if parser.peek() != '@' {
+                    return Ok(None);
+                }
+                let start = parser.position();
+                parser.take();
+                while parser.peek().is_alphanumeric() {
+                    parser.take();
+                }
+                let name = Element::Text(parser.source_from(start).into(), parser.span_from(start));
+                Ok(Some(Element::ExtensionElement(MENTION, vec![name], parser.span_from(start))))
+            }
+
+            fn trigger_characters(&self) -> Option<Vec<char>> {
+                Some(vec!['@'])
+            }
+        }
+
+        let tries = Arc::new(AtomicUsize::new(0));
+        let mut parser = Parser::new("Thanks @alice and *@bob*, and ünïcode.");
+        parser.add_extension(Bold);
+        parser.add_extension(Mentions(tries.clone()));
+
+        let mut emitter = HtmlEmitter::new();
+        emitter.tagged_element(MENTION, |_| ("cite".into(), vec![]));
+
+        assert_eq!(
+            parser.emit_with(&emitter).unwrap(),
+            "<p>\n  Thanks <cite>@alice</cite> and <strong><cite>@bob</cite></strong>, and ünïcode.\n</p>\n"
+        );
+        assert_eq!(tries.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn fully_external_extension_printed() {
+        const MATH: ExtensionTag = ExtensionTag::from_static("MY_MATH");
+
+        struct Math;
+
+        impl Extension for Math {
+            fn parse_element<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Element<'a>>> {
+                if parser.peek() != '$' {
+                    return Ok(None);
+                }
+                let start = parser.position();
+                parser.take();
+                let content_start = parser.position();
+                while !parser.is_at_end() && parser.peek() != '$' {
+                    parser.take();
+                }
+                let content = parser.source_from(content_start).into();
+                parser.take();
+                let span = parser.span_from(start);
+                Ok(Some(Element::ExtensionElement(MATH, vec![Element::Text(content, span)], span)))
+            }
+
+            fn print_element(&self, printer: &PublicationEmitter, element: &Element, out: &mut Printout) -> bool {
+                match element {
+                    Element::ExtensionElement(MATH, elements, _) => {
+                        out.push('$');
+                        printer.print_elements(elements, out);
+                        out.push('$');
+                        true
+                    }
+                    _ => false,
+                }
+            }
+        }
+
+        let mut parser = Parser::new("Both $a  +  b$ and *$c$*.");
+        parser.add_extension(Math);
+        parser.add_extension(Bold);
+
+        let emitter = parser.publication_emitter();
+        assert_eq!(parser.emit_with(&emitter).unwrap(), "Both $a  +  b$ and *$c$*.\n");
+    }
 }
@@ -0,0 +1,362 @@
+use crate::extensions::{self, BOLD, ITALICS, LIST, LIST_ITEM, ORDERED_LIST};
+use crate::{Block, CommentSyntax, Element, PublicationEmitter, Span, Trivia};
+use pulldown_cmark::{Event, OffsetIter, Tag};
+use std::iter::Peekable;
+use std::ops::Range;
+
+/// Converts CommonMark documents into Publication source, using the syntax of
+/// the extensions that are enabled on the importer. Anything that can't be
+/// expressed with those extensions is kept as plain text where possible, and
+/// listed in the `losses` of the import.
+///
+/// The document is converted to blocks and printed with a
+/// `PublicationEmitter`, so paragraphs end up on a single line.
+pub struct MarkdownImporter {
+    bold: bool,
+    italics: bool,
+    bullet: Option<String>,
+    ordered_lists: bool,
+    comment_syntax: CommentSyntax,
+}
+
+pub struct MarkdownImport {
+    pub source: String,
+    pub losses: Vec<Loss>,
+}
+
+/// A Markdown construct that could not be carried over to Publication.
+/// `extension` names the built-in extension that would have kept it, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loss {
+    pub construct: &'static str,
+    pub line: usize,
+    pub extension: Option<&'static str>,
+}
+
+impl MarkdownImporter {
+    pub fn new() -> MarkdownImporter {
+        MarkdownImporter {
+            bold: false,
+            italics: false,
+            bullet: None,
+            ordered_lists: false,
+            comment_syntax: CommentSyntax::default(),
+        }
+    }
+
+    pub fn bold(&mut self) {
+        self.bold = true;
+    }
+
+    pub fn italics(&mut self) {
+        self.italics = true;
+    }
+
+    pub fn lists<B: AsRef<str>>(&mut self, bullet: B) {
+        self.bullet = Some(bullet.as_ref().into());
+    }
+
+    pub fn ordered_lists(&mut self) {
+        self.ordered_lists = true;
+    }
+
+    /// Passed on to the emitter that prints the output, as with
+    /// `PublicationEmitter::comment_syntax`.
+    pub fn comment_syntax(&mut self, syntax: CommentSyntax) {
+        self.comment_syntax = syntax;
+    }
+
+    pub fn import(&self, markdown: &str) -> MarkdownImport {
+        let mut converter = Converter {
+            importer: self,
+            events: pulldown_cmark::Parser::new(markdown).into_offset_iter().peekable(),
+            line_starts: std::iter::once(0)
+                .chain(markdown.match_indices('\n').map(|(i, _)| i + 1))
+                .collect(),
+            losses: vec![],
+        };
+
+        let blocks = converter.blocks();
+        MarkdownImport {
+            source: self.printer().print_document(&blocks, &Trivia::default()),
+            losses: converter.losses,
+        }
+    }
+
+    // Prints blocks with the extensions that are enabled.
+    fn printer(&self) -> PublicationEmitter {
+        let mut printer = PublicationEmitter::new();
+        if self.bold {
+            printer.add_extension(extensions::Bold);
+        }
+        if self.italics {
+            printer.add_extension(extensions::Italics);
+        }
+        if let Some(bullet) = &self.bullet {
+            printer.add_extension(extensions::Lists::new(bullet));
+        }
+        if self.ordered_lists {
+            printer.add_extension(extensions::OrderedLists);
+        }
+        printer.comment_syntax(self.comment_syntax.clone());
+        printer
+    }
+}
+
+impl Default for MarkdownImporter {
+    fn default() -> MarkdownImporter {
+        MarkdownImporter::new()
+    }
+}
+
+struct Converter<'a, 'i> {
+    importer: &'i MarkdownImporter,
+    events: Peekable<OffsetIter<'a>>,
+    line_starts: Vec<usize>,
+    losses: Vec<Loss>,
+}
+
+impl<'a, 'i> Converter<'a, 'i> {
+    fn lose(&mut self, construct: &'static str, range: &Range<usize>, extension: Option<&'static str>) {
+        let line = match self.line_starts.binary_search(&range.start) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        self.losses.push(Loss {
+            construct,
+            line,
+            extension,
+        });
+    }
+
+    /// Converts blocks up to, but not including, the end of the enclosing tag.
+    fn blocks(&mut self) -> Vec<Block<'static>> {
+        let mut blocks = vec![];
+        while let Some((event, range)) = self.events.peek().cloned() {
+            match event {
+                Event::End(_) => break,
+                // Tight list items contain their text without a paragraph.
+                event if is_inline(&event) => {
+                    let elements = self.inlines();
+                    if !elements.is_empty() {
+                        blocks.push(Block::Paragraph(elements, Span::default()));
+                    }
+                }
+                event => {
+                    self.events.next();
+                    self.block(event, range, &mut blocks);
+                }
+            }
+        }
+        blocks
+    }
+
+    fn block(&mut self, event: Event<'a>, range: Range<usize>, blocks: &mut Vec<Block<'static>>) {
+        match event {
+            Event::Start(Tag::Paragraph) => {
+                let elements = self.inlines();
+                self.events.next(); // End
+                if !elements.is_empty() {
+                    blocks.push(Block::Paragraph(elements, Span::default()));
+                }
+            }
+            Event::Start(Tag::List(start)) => {
+                let enabled = match start {
+                    None => self.importer.bullet.is_some(),
+                    Some(_) => self.importer.ordered_lists,
+                };
+                match enabled {
+                    true => blocks.push(self.list(start)),
+                    false => {
+                        let extension = if start.is_some() { "OrderedLists" } else { "Lists" };
+                        self.lose("list", &range, Some(extension));
+                        while let Some((Event::Start(Tag::Item), _)) = self.events.peek() {
+                            self.events.next();
+                            blocks.append(&mut self.blocks());
+                            self.events.next(); // End(Item)
+                        }
+                        self.events.next(); // End(List)
+                    }
+                }
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                self.lose("code block", &range, None);
+                let mut code = String::new();
+                while let Some((Event::Text(text), _)) = self.events.peek() {
+                    code.push_str(text.as_ref());
+                    self.events.next();
+                }
+                self.events.next(); // End
+                // The lines of the code are read back as a single paragraph
+                // anyway, and would otherwise be taken for blocks of their own.
+                let code = code.trim_end().replace('\n', " ");
+                if !code.is_empty() {
+                    blocks.push(Block::Paragraph(vec![Element::Text(code.into(), Span::default())], Span::default()));
+                }
+            }
+            Event::Start(Tag::HtmlBlock) => {
+                self.lose("HTML", &range, None);
+                self.skip();
+            }
+            Event::Start(tag) => {
+                self.lose(block_construct(&tag), &range, None);
+                blocks.append(&mut self.blocks());
+                self.events.next(); // End
+            }
+            Event::Rule => self.lose("thematic break", &range, None),
+            Event::Html(_) => self.lose("HTML", &range, None),
+            _ => {}
+        }
+    }
+
+    fn list(&mut self, start: Option<u64>) -> Block<'static> {
+        let mut items = vec![];
+        while let Some((Event::Start(Tag::Item), _)) = self.events.peek() {
+            self.events.next();
+            let blocks = self.blocks();
+            self.events.next(); // End(Item)
+            items.push(Block::ExtensionBlocks(LIST_ITEM, blocks, vec![], Span::default()));
+        }
+        self.events.next(); // End(List)
+
+        match start {
+            Some(start) => {
+                let attributes = vec![
+                    ("start".into(), start.to_string()),
+                    ("type".into(), "1".into()),
+                    ("delimiter".into(), ".".into()),
+                ];
+                Block::ExtensionBlocks(ORDERED_LIST, items, attributes, Span::default())
+            }
+            None => Block::ExtensionBlocks(LIST, items, vec![], Span::default()),
+        }
+    }
+
+    /// Converts inline content up to the end of the enclosing tag, or up to
+    /// the next block in a tight list item.
+    fn inlines(&mut self) -> Vec<Element<'static>> {
+        let mut elements = vec![];
+        while let Some((event, range)) = self.events.peek().cloned() {
+            if !is_inline(&event) {
+                break;
+            }
+            self.events.next();
+            match event {
+                Event::Text(text) => push_text(&mut elements, text.as_ref()),
+                Event::SoftBreak => push_text(&mut elements, " "),
+                Event::HardBreak => {
+                    self.lose("hard line break", &range, None);
+                    push_text(&mut elements, " ");
+                }
+                Event::Code(code) => {
+                    self.lose("inline code", &range, None);
+                    push_text(&mut elements, code.as_ref());
+                }
+                Event::Start(Tag::Strong) => {
+                    let inner = self.inlines();
+                    self.events.next(); // End
+                    if self.importer.bold {
+                        elements.push(Element::ExtensionElement(BOLD, inner, Span::default()));
+                    } else {
+                        self.lose("strong emphasis", &range, Some("Bold"));
+                        push_elements(&mut elements, inner);
+                    }
+                }
+                Event::Start(Tag::Emphasis) => {
+                    let inner = self.inlines();
+                    self.events.next(); // End
+                    if self.importer.italics {
+                        elements.push(Element::ExtensionElement(ITALICS, inner, Span::default()));
+                    } else {
+                        self.lose("emphasis", &range, Some("Italics"));
+                        push_elements(&mut elements, inner);
+                    }
+                }
+                Event::Start(tag) => {
+                    self.lose(inline_construct(&tag), &range, None);
+                    let inner = self.inlines();
+                    push_elements(&mut elements, inner);
+                    self.events.next(); // End
+                }
+                Event::InlineHtml(_) => self.lose("HTML", &range, None),
+                Event::FootnoteReference(_) => self.lose("footnote", &range, None),
+                _ => {}
+            }
+        }
+        elements
+    }
+
+    fn skip(&mut self) {
+        let mut depth = 0;
+        for (event, _) in self.events.by_ref() {
+            match event {
+                Event::Start(_) => depth += 1,
+                Event::End(_) if depth == 0 => return,
+                Event::End(_) => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+// Adds text to the text before it, if any, so that it is escaped as a whole.
+fn push_text(elements: &mut Vec<Element<'static>>, text: &str) {
+    match elements.last_mut() {
+        Some(Element::Text(last, _)) => last.to_mut().push_str(text),
+        _ => elements.push(Element::Text(text.to_string().into(), Span::default())),
+    }
+}
+
+fn push_elements(elements: &mut Vec<Element<'static>>, inner: Vec<Element<'static>>) {
+    for element in inner {
+        match element {
+            Element::Text(text, _) => push_text(elements, text.as_ref()),
+            element => elements.push(element),
+        }
+    }
+}
+
+fn is_inline(event: &Event) -> bool {
+    match event {
+        Event::Start(tag) => matches!(
+            tag,
+            Tag::Emphasis
+                | Tag::Strong
+                | Tag::Strikethrough
+                | Tag::Superscript
+                | Tag::Subscript
+                | Tag::Link { .. }
+                | Tag::Image { .. }
+        ),
+        Event::End(_) => false,
+        Event::Text(_)
+        | Event::Code(_)
+        | Event::InlineMath(_)
+        | Event::InlineHtml(_)
+        | Event::FootnoteReference(_)
+        | Event::SoftBreak
+        | Event::HardBreak
+        | Event::TaskListMarker(_) => true,
+        _ => false,
+    }
+}
+
+fn block_construct(tag: &Tag) -> &'static str {
+    match tag {
+        Tag::Heading { .. } => "heading",
+        Tag::BlockQuote(_) => "block quote",
+        Tag::FootnoteDefinition(_) => "footnote",
+        Tag::Table(_) | Tag::TableHead | Tag::TableRow | Tag::TableCell => "table",
+        _ => "block",
+    }
+}
+
+fn inline_construct(tag: &Tag) -> &'static str {
+    match tag {
+        Tag::Link { .. } => "link",
+        Tag::Image { .. } => "image",
+        Tag::Strikethrough => "strikethrough",
+        _ => "inline formatting",
+    }
+}
+
@@ -1,16 +1,117 @@
 mod emitter;
 pub use self::emitter::*;
 
+mod span;
+pub use self::span::*;
+
+mod diagnostic;
+pub use self::diagnostic::*;
+
+mod import;
+pub use self::import::*;
+
+mod trivia;
+pub use self::trivia::*;
+
+mod dialect;
+pub use self::dialect::*;
+
+mod reader;
+pub use self::reader::*;
+
+mod document;
+pub use self::document::*;
+
 pub mod extensions;
 use self::extensions::Extension;
 
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::convert::TryInto;
 use std::fmt;
+use std::io;
+use std::ops::Range;
 use std::path::Path;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A name shared by extension tags and diagnostic codes. Names compare by
+/// text, so a name made at runtime with `Name::new` is equal to a constant
+/// name with the same text.
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Name(&'static str);
+
+impl Name {
+    /// A name known at compile time, for use in constants.
+    pub const fn from_static(name: &'static str) -> Name {
+        Name(name)
+    }
+
+    /// A name that is only known at runtime, like one read from a
+    /// configuration file. Names are interned, so each distinct name is only
+    /// ever allocated once.
+    pub fn new(name: &str) -> Name {
+        static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+        let mut names = NAMES.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+        match names.get(name) {
+            Some(name) => Name(name),
+            None => {
+                let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+                names.insert(name);
+                Name(name)
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.0, f)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Name {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Name {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Name, D::Error> {
+        let name = <Cow<str>>::deserialize(deserializer)?;
+        Ok(Name::new(name.as_ref()))
+    }
+}
+
+/// Identifies the blocks and elements of an extension. Tags are constants, so
+/// they can be matched on in patterns.
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub struct ExtensionTag(Name);
 
-#[derive(PartialEq, Eq, Hash)]
-pub struct ExtensionTag(&'static str);
+impl ExtensionTag {
+    pub const fn from_static(name: &'static str) -> ExtensionTag {
+        ExtensionTag(Name::from_static(name))
+    }
+
+    pub fn new(name: &str) -> ExtensionTag {
+        ExtensionTag(Name::new(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0.as_str()
+    }
+}
 
 impl fmt::Debug for ExtensionTag {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -18,51 +119,194 @@ impl fmt::Debug for ExtensionTag {
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Block {
-    Paragraph(Vec<Element>),
-    ExtensionBlocks(ExtensionTag, Vec<Block>),
-    ExtensionBlock(ExtensionTag, Vec<Element>),
+impl fmt::Display for ExtensionTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for ExtensionTag {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Element {
-    Text(String),
-    ExtensionElement(ExtensionTag, Box<Element>),
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ExtensionTag {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<ExtensionTag, D::Error> {
+        Name::deserialize(deserializer).map(ExtensionTag)
+    }
 }
 
-#[derive(Debug)]
-pub enum ParseError {
-    UnexpectedEndOfFile,
+pub type Attributes = Vec<(String, String)>;
+
+/// A block of a document. The text of its elements is borrowed from the
+/// source wherever possible; use `into_owned` to keep a block around longer
+/// than the source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block<'a> {
+    Paragraph(Vec<Element<'a>>, Span),
+    ExtensionBlocks(ExtensionTag, Vec<Block<'a>>, Attributes, Span),
+    ExtensionBlock(ExtensionTag, Vec<Element<'a>>, Span),
 }
 
-impl fmt::Display for ParseError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl<'a> Block<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            Block::Paragraph(_, span)
+            | Block::ExtensionBlocks(_, _, _, span)
+            | Block::ExtensionBlock(_, _, span) => *span,
+        }
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        match self {
+            Block::ExtensionBlocks(_, _, attributes, _) => attribute(attributes, name),
+            _ => None,
+        }
+    }
+
+    /// Copies any text borrowed from the source.
+    pub fn into_owned(self) -> Block<'static> {
+        match self {
+            Block::Paragraph(elements, span) => Block::Paragraph(into_owned(elements), span),
+            Block::ExtensionBlocks(tag, blocks, attributes, span) => {
+                Block::ExtensionBlocks(tag, blocks.into_iter().map(Block::into_owned).collect(), attributes, span)
+            }
+            Block::ExtensionBlock(tag, elements, span) => Block::ExtensionBlock(tag, into_owned(elements), span),
+        }
+    }
+
+    /// Moves every span in the block, for when the source around it changed.
+    pub(crate) fn map_positions<F: Fn(Position) -> Position>(&mut self, f: &F) {
+        match self {
+            Block::Paragraph(elements, span) | Block::ExtensionBlock(_, elements, span) => {
+                elements.iter_mut().for_each(|element| element.map_positions(f));
+                *span = span.map(f);
+            }
+            Block::ExtensionBlocks(_, blocks, _, span) => {
+                blocks.iter_mut().for_each(|block| block.map_positions(f));
+                *span = span.map(f);
+            }
+        }
+    }
+}
+
+pub fn attribute<'a>(attributes: &'a Attributes, name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_ref())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element<'a> {
+    Text(Cow<'a, str>, Span),
+    ExtensionElement(ExtensionTag, Vec<Element<'a>>, Span),
+}
+
+impl<'a> Element<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            Element::Text(_, span) | Element::ExtensionElement(_, _, span) => *span,
+        }
+    }
+
+    /// Copies any text borrowed from the source.
+    pub fn into_owned(self) -> Element<'static> {
+        match self {
+            Element::Text(text, span) => Element::Text(Cow::Owned(text.into_owned()), span),
+            Element::ExtensionElement(tag, elements, span) => {
+                Element::ExtensionElement(tag, into_owned(elements), span)
+            }
+        }
+    }
+
+    pub(crate) fn map_positions<F: Fn(Position) -> Position>(&mut self, f: &F) {
         match self {
-            ParseError::UnexpectedEndOfFile => write!(f, "Unexpected end of file."),
+            Element::Text(_, span) => *span = span.map(f),
+            Element::ExtensionElement(_, elements, span) => {
+                elements.iter_mut().for_each(|element| element.map_positions(f));
+                *span = span.map(f);
+            }
         }
     }
 }
 
-type ParseResult<T> = Result<T, ParseError>;
+fn into_owned(elements: Vec<Element>) -> Vec<Element<'static>> {
+    elements.into_iter().map(Element::into_owned).collect()
+}
+
+pub type ParseResult<T> = Result<T, Box<Diagnostic>>;
+
+// Extensions are shared by the parsers of a dialect, and cloned by the parser
+// every time it tries them, so cloning has to be cheap.
+pub(crate) type Extensions = Arc<extensions::ExtensionSet>;
+
+/// Parses a document directly from its source. Positions are byte offsets
+/// into the source, and text is borrowed from it unless whitespace had to be
+/// collapsed or escapes removed.
+pub struct Parser<'a> {
+    raw: &'a str,
+    position: Position,
+    content_end: Position,
+    extensions: Extensions,
+    delimiters: Vec<String>,
+    // The numbering and delimiter of each ordered list being parsed, whose
+    // next items end a paragraph.
+    ordered_lists: Vec<(extensions::Numbering, char)>,
+    diagnostics: Vec<Diagnostic>,
+    // The code, span and message of every diagnostic reported so far.
+    reported: HashSet<(Option<DiagnosticCode>, Span, String)>,
+    deny_warnings: bool,
+    comment_syntax: CommentSyntax,
+    comments: Vec<Comment>,
+}
 
-pub struct Parser {
-    raw: Vec<char>,
-    offset: usize,
-    extensions: Vec<Rc<dyn Extension>>,
+#[derive(Clone, Copy)]
+struct Checkpoint {
+    position: Position,
+    content_end: Position,
+    comments: usize,
 }
 
-impl Parser {
-    pub fn new(raw: String) -> Parser {
+impl<'a> Parser<'a> {
+    pub fn new(raw: &'a str) -> Parser<'a> {
         Parser {
-            raw: raw.chars().collect(),
-            offset: 0,
-            extensions: vec![],
+            raw,
+            position: Position::default(),
+            content_end: Position::default(),
+            extensions: Extensions::default(),
+            delimiters: vec![],
+            ordered_lists: vec![],
+            diagnostics: vec![],
+            reported: HashSet::new(),
+            deny_warnings: false,
+            comment_syntax: CommentSyntax::default(),
+            comments: vec![],
         }
     }
 
     pub fn add_extension<E: Extension + 'static>(&mut self, extension: E) {
-        self.extensions.push(Rc::new(extension));
+        Arc::make_mut(&mut self.extensions).push(Arc::new(extension));
+    }
+
+    pub fn comment_syntax(&mut self, syntax: CommentSyntax) {
+        self.comment_syntax = syntax;
+    }
+
+    /// A `PublicationEmitter` that prints with the extensions and comment
+    /// syntax of this parser.
+    pub fn publication_emitter(&self) -> PublicationEmitter {
+        let mut emitter = PublicationEmitter::with_extensions(self.extensions.clone());
+        emitter.comment_syntax(self.comment_syntax.clone());
+        emitter
+    }
+
+    /// Turns every warning reported during parsing into an error.
+    pub fn deny_warnings(&mut self) {
+        self.deny_warnings = true;
     }
 
     pub fn emit_with(mut self, emitter: &dyn Emitter) -> ParseResult<String> {
@@ -75,153 +319,505 @@ impl Parser {
         Ok(out)
     }
 
-    pub fn parse(mut self) -> ParseResult<Vec<Block>> {
+    /// Like `emit_with`, but writes each block to `writer` as soon as it has
+    /// been parsed, instead of returning the whole output.
+    pub fn emit_to<W: io::Write>(self, emitter: &dyn Emitter, mut writer: W) -> Result<(), EmitError> {
+        self.emit_each(emitter, |text| writer.write_all(text.as_bytes()).map_err(EmitError::from))?;
+        Ok(writer.flush()?)
+    }
+
+    pub fn emit_to_fmt<W: fmt::Write>(self, emitter: &dyn Emitter, mut writer: W) -> Result<(), EmitError> {
+        self.emit_each(emitter, |text| writer.write_str(text).map_err(EmitError::from))
+    }
+
+    /// Like `emit_recovering`, but writes each block to `writer` as soon as it
+    /// has been parsed. Only a failure to write stops the parser early.
+    pub fn emit_recovering_to<W: io::Write>(
+        mut self,
+        emitter: &dyn Emitter,
+        mut writer: W,
+    ) -> io::Result<Vec<Diagnostic>> {
+        let mut stream = StreamingEmitter::new(emitter);
+        self.move_past_whitespace();
+        while !self.is_at_end() {
+            if let Some(block) = self.parse_block_recovering() {
+                writer.write_all(stream.emit(block).as_bytes())?;
+            }
+            self.move_past_whitespace();
+        }
+        writer.flush()?;
+        Ok(self.diagnostics)
+    }
+
+    fn emit_each<F>(mut self, emitter: &dyn Emitter, mut write: F) -> Result<(), EmitError>
+    where
+        F: FnMut(&str) -> Result<(), EmitError>,
+    {
+        let mut stream = StreamingEmitter::new(emitter);
+        self.move_past_whitespace();
+        while !self.is_at_end() {
+            write(stream.emit(self.parse_block()?))?;
+            self.move_past_whitespace();
+        }
+        Ok(())
+    }
+
+    pub fn parse(self) -> ParseResult<Vec<Block<'a>>> {
+        self.parse_with_comments().map(|(blocks, _)| blocks)
+    }
+
+    /// Like `parse`, but also hands back the comments of the source, in order,
+    /// for tools that need to write them back out.
+    pub fn parse_with_comments(mut self) -> ParseResult<(Vec<Block<'a>>, Vec<Comment>)> {
         let mut out = vec![];
         self.move_past_whitespace();
         while !self.is_at_end() {
             out.push(self.parse_block()?);
             self.move_past_whitespace();
         }
-        Ok(out)
+        Ok((out, self.comments))
+    }
+
+    /// Parses without losing anything but whitespace. Comments are attached
+    /// to the blocks around them in a `Trivia` table.
+    pub fn parse_lossless(self) -> ParseResult<(Vec<Block<'a>>, Trivia)> {
+        let (blocks, comments) = self.parse_with_comments()?;
+        let trivia = Trivia::attach(&blocks, comments);
+        Ok((blocks, trivia))
+    }
+
+    /// Like `emit_with`, but instead of stopping at the first error, the
+    /// parser skips to the next block boundary and keeps going. Blocks that
+    /// failed to parse are left out of the output.
+    pub fn emit_recovering(mut self, emitter: &dyn Emitter) -> (String, Vec<Diagnostic>) {
+        let mut out = String::new();
+        self.move_past_whitespace();
+        while !self.is_at_end() {
+            if let Some(block) = self.parse_block_recovering() {
+                emitter.emit_block(block, &mut out);
+            }
+            self.move_past_whitespace();
+        }
+        (out, self.diagnostics)
+    }
+
+    /// Like `parse`, but instead of stopping at the first error, the parser
+    /// skips to the next block boundary and keeps going. Blocks that failed
+    /// to parse are left out of the result.
+    pub fn parse_recovering(mut self) -> (Vec<Block<'a>>, Vec<Diagnostic>) {
+        let mut out = vec![];
+        self.move_past_whitespace();
+        while !self.is_at_end() {
+            if let Some(block) = self.parse_block_recovering() {
+                out.push(block);
+            }
+            self.move_past_whitespace();
+        }
+        (out, self.diagnostics)
+    }
+
+    /// Records a diagnostic, typically a warning, without aborting the parse.
+    /// Only the recovering parse modes hand these back to the caller.
+    ///
+    /// If warnings are denied, the diagnostic is instead returned as an error,
+    /// which the extension is expected to propagate.
+    pub fn report(&mut self, mut diagnostic: Diagnostic) -> ParseResult<()> {
+        if self.deny_warnings {
+            diagnostic.severity = Severity::Error;
+            return Err(Box::new(diagnostic));
+        }
+        // Backtracking can make the parser see the same source twice.
+        if self.reported.insert((diagnostic.code, diagnostic.span, diagnostic.message.clone())) {
+            self.diagnostics.push(diagnostic);
+        }
+        Ok(())
+    }
+
+    /// Parses inline content up to and including `delimiter`, letting inline
+    /// extensions contain other elements. Returns `None` if the block, or an
+    /// enclosing element, ends before `delimiter` is found.
+    pub fn parse_elements_until(&mut self, delimiter: &str) -> ParseResult<Option<Vec<Element<'a>>>> {
+        self.delimiters.push(delimiter.into());
+        let elements = self.parse_elements();
+        self.delimiters.pop();
+        let elements = elements?;
+
+        if self.rest_bytes().starts_with(delimiter.as_bytes()) {
+            self.take_many(delimiter.chars().count());
+            Ok(Some(elements))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Whether the next character is a backslash escaping the one after it.
+    /// Any character but letters, digits and whitespace can be escaped.
+    pub fn sees_escape(&self) -> bool {
+        if !self.rest_bytes().starts_with(b"\\") {
+            return false;
+        }
+        let escaped = self.peek_second();
+        !escaped.is_alphanumeric() && !escaped.is_whitespace() && escaped != '\0'
+    }
+
+    /// Consumes an escape sequence, returning the escaped character. Extensions
+    /// that read raw text should use this so that their delimiters can be
+    /// escaped too.
+    pub fn take_escaped(&mut self) -> Option<char> {
+        if self.sees_escape() {
+            self.advance();
+            Some(self.take())
+        } else {
+            None
+        }
+    }
+
+    /// The position of the next character to be consumed.
+    #[inline]
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// A span from `start` to the end of the last non-whitespace character
+    /// consumed, so that trailing whitespace and comments are not included.
+    pub fn span_from(&self, start: Position) -> Span {
+        Span::new(start, self.content_end.max(start))
+    }
+
+    /// The source from `start` up to the next character to be consumed, for
+    /// extensions that keep raw text without copying it.
+    pub fn source_from(&self, start: Position) -> &'a str {
+        &self.raw[start.offset..self.position.offset]
+    }
+
+    #[inline]
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            position: self.position,
+            content_end: self.content_end,
+            comments: self.comments.len(),
+        }
+    }
+
+    #[inline]
+    fn restore(&mut self, checkpoint: Checkpoint) {
+        self.position = checkpoint.position;
+        self.content_end = checkpoint.content_end;
+        self.comments.truncate(checkpoint.comments);
+    }
+
+    fn is_at_start_of_line(&self) -> bool {
+        self.raw[..self.position.offset]
+            .chars()
+            .rev()
+            .take_while(|c| *c != '\n')
+            .all(|c| c.is_whitespace())
     }
 
     #[inline]
     fn is_at_end(&self) -> bool {
-        self.raw.len() == self.offset
+        self.position.offset >= self.raw.len()
+    }
+
+    /// The source that is yet to be consumed.
+    #[inline]
+    fn rest(&self) -> &'a str {
+        &self.raw[self.position.offset..]
     }
 
+    /// Like `rest`, but cheaper for comparisons, since the position doesn't
+    /// have to be checked to be at a character boundary.
     #[inline]
-    fn peek_at(&self, offset: usize) -> char {
-        self.raw.get(offset).cloned().unwrap_or('\0')
+    fn rest_bytes(&self) -> &'a [u8] {
+        &self.raw.as_bytes()[self.position.offset..]
     }
 
     #[inline]
     fn peek(&self) -> char {
-        self.peek_at(self.offset)
+        self.rest().chars().next().unwrap_or('\0')
     }
 
-    fn peek_many(&self, len: usize) -> &[char] {
-        let raw_len = self.raw.len();
-        if raw_len > self.offset + len {
-            &self.raw[self.offset..self.offset+len]
-        } else if raw_len > self.offset {
-            &self.raw[self.offset..]
-        } else {
-            &[]
+    #[inline]
+    fn peek_second(&self) -> char {
+        self.rest().chars().nth(1).unwrap_or('\0')
+    }
+
+    /// Up to `len` characters of the source that is yet to be consumed.
+    fn peek_many(&self, len: usize) -> &'a str {
+        let rest = self.rest();
+        match rest.char_indices().nth(len) {
+            Some((end, _)) => &rest[..end],
+            None => rest,
         }
     }
 
+    #[inline]
+    fn advance(&mut self) {
+        self.take();
+    }
+
     #[inline]
     fn take(&mut self) -> char {
-        let c = self.peek();
-        self.offset += 1;
-        c
+        match self.rest().chars().next() {
+            Some(c) => {
+                self.position.advance(c);
+                if !c.is_whitespace() {
+                    self.content_end = self.position;
+                }
+                c
+            }
+            None => '\0',
+        }
     }
 
     #[inline]
-    fn take_many(&mut self, len: usize) -> Vec<char> {
-        let c = self.peek_many(len).to_vec();
-        self.offset += len;
-        c
+    fn take_many(&mut self, len: usize) -> &'a str {
+        let taken = self.peek_many(len);
+        for _ in taken.chars() {
+            self.advance();
+        }
+        taken
     }
 
     fn move_past_whitespace(&mut self) {
         loop {
             match self.peek() {
-                '#' => self.move_past_comment(),
-                w if w.is_whitespace() => self.offset += 1,
+                _ if self.sees_comment() => self.move_past_comment(),
+                w if w.is_whitespace() => self.advance(),
                 _ => break,
             }
         }
     }
 
+    fn sees(&self, text: &str) -> bool {
+        !text.is_empty() && self.rest_bytes().starts_with(text.as_bytes())
+    }
+
+    fn sees_comment(&self) -> bool {
+        let syntax = &self.comment_syntax;
+        syntax.line.as_ref().map(|marker| self.sees(marker)).unwrap_or(false)
+            || syntax.block.as_ref().map(|(open, _)| self.sees(open)).unwrap_or(false)
+    }
+
+    /// Moves past a line or block comment. A block comment that is never
+    /// closed runs to the end of the file.
     fn move_past_comment(&mut self) {
-        loop {
-            match self.peek() {
-                '\n' => {
-                    self.offset += 1;
-                    return;
+        let content_end = self.content_end;
+        let start = self.position();
+        let close = match self.comment_syntax.block.clone() {
+            Some((open, close)) if self.sees(open.as_ref()) => {
+                self.take_many(open.chars().count());
+                Some(close)
+            }
+            _ => None,
+        };
+        match close {
+            Some(close) => {
+                while !self.is_at_end() && !self.sees(close.as_ref()) {
+                    self.advance();
+                }
+                if self.sees(close.as_ref()) {
+                    self.take_many(close.chars().count());
+                }
+            }
+            None => {
+                while !matches!(self.peek(), '\n' | '\0') {
+                    self.advance();
+                }
+            }
+        }
+        // Lines after the first are kept relative to the column the comment
+        // starts at, so that it can be printed at another one.
+        let lines: Vec<&str> = self
+            .source_from(start)
+            .trim_end()
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| match i {
+                0 => line,
+                _ => {
+                    let spaces = line.len() - line.trim_start_matches(' ').len();
+                    &line[spaces.min(start.column - 1)..]
                 }
-                '\0' => return,
-                _ => self.offset += 1,
+            })
+            .collect();
+        self.comments.push(Comment {
+            text: lines.join("\n"),
+            span: self.span_from(start),
+        });
+        // The newline is left in place, since it may be part of a paragraph break.
+        self.content_end = content_end;
+    }
+
+    fn parse_block_recovering(&mut self) -> Option<Block<'a>> {
+        let offset_before_block = self.position.offset;
+        match self.parse_block() {
+            Ok(block) => Some(block),
+            Err(diagnostic) => {
+                self.diagnostics.push(*diagnostic);
+                self.move_to_end_of_block();
+                if self.position.offset == offset_before_block {
+                    self.advance();
+                    self.move_to_end_of_block();
+                }
+                None
             }
         }
     }
 
-    fn parse_block(&mut self) -> ParseResult<Block> {
-        for ext in self.extensions.clone() {
-            let offset_before_ext = self.offset;
+    fn move_to_end_of_block(&mut self) {
+        while !self.is_at_end() && !self.sees_end_of_block() {
+            self.advance();
+        }
+    }
+
+    fn parse_block(&mut self) -> ParseResult<Block<'a>> {
+        let extensions = self.extensions.clone();
+        for ext in extensions.triggered_by(self.peek()) {
+            let checkpoint = self.checkpoint();
             if let Some(block) = ext.parse_block(self)? {
                 return Ok(block);
             }
-            self.offset = offset_before_ext;
+            self.restore(checkpoint);
         }
         self.parse_paragraph_block()
     }
 
-    fn parse_paragraph_block(&mut self) -> ParseResult<Block> {
+    fn parse_paragraph_block(&mut self) -> ParseResult<Block<'a>> {
         if self.is_at_end() {
-            return Err(ParseError::UnexpectedEndOfFile);
+            return Err(Box::new(
+                Diagnostic::error(self.span_from(self.position()), "unexpected end of file")
+                    .with_code(UNEXPECTED_END_OF_FILE)
+                    .with_expected("a paragraph"),
+            ));
         }
 
-        Ok(Block::Paragraph(self.parse_elements()?))
+        let start = self.position();
+        let elements = self.parse_elements()?;
+        Ok(Block::Paragraph(elements, self.span_from(start)))
     }
 
-    fn parse_elements(&mut self) -> ParseResult<Vec<Element>> {
+    fn parse_elements(&mut self) -> ParseResult<Vec<Element<'a>>> {
         let mut elements = vec![];
 
-        let mut paragraph = String::new();
-        let mut whitespace = false;
-        'elements: while !self.sees_end_of_block() {
-            for ext in self.extensions.clone() {
-                let offset_before_ext = self.offset;
-                if let Some(el) = ext.parse_element(self)? {
-                    if whitespace {
-                        paragraph.push(' ');
-                        whitespace = false;
+        let extensions = self.extensions.clone();
+        let mut paragraph = Text::new(self.raw);
+        let mut paragraph_start = None;
+        let mut whitespace_start: Option<Position> = None;
+        'elements: while !self.sees_end_of_block() && !self.sees_delimiter() {
+            // An escaped character is always text.
+            if !self.sees_escape() {
+                for ext in extensions.triggered_by(self.peek()) {
+                    let checkpoint = self.checkpoint();
+                    if let Some(el) = ext.parse_element(self)? {
+                        if let Some(start) = whitespace_start.take() {
+                            paragraph.push(start.offset, " ");
+                            paragraph_start.get_or_insert(start);
+                        }
+                        if let Some(start) = paragraph_start.take() {
+                            elements.push(Element::Text(paragraph.take(), Span::new(start, el.span().start)));
+                        }
+                        elements.push(el);
+                        continue 'elements;
                     }
-                    if !paragraph.is_empty() {
-                        elements.push(Element::Text(std::mem::replace(
-                            &mut paragraph,
-                            String::new(),
-                        )));
-                    }
-                    elements.push(el);
-                    continue 'elements;
+                    self.restore(checkpoint);
+                }
+
+                if self.sees_comment() {
+                    self.move_past_comment();
+                    continue;
                 }
-                self.offset = offset_before_ext;
             }
 
-            match self.take() {
-                '#' => self.move_past_comment(),
+            let start = self.position();
+            match self.take_escaped().unwrap_or_else(|| self.take()) {
                 w if w.is_whitespace() => {
-                    whitespace |= true;
+                    whitespace_start.get_or_insert(start);
                 }
                 c => {
-                    if whitespace {
-                        paragraph.push(' ');
-                        whitespace = false;
+                    if let Some(start) = whitespace_start.take() {
+                        paragraph.push(start.offset, " ");
+                        paragraph_start.get_or_insert(start);
                     }
-                    paragraph.push(c)
+                    paragraph_start.get_or_insert(start);
+                    paragraph.push_source(self.position.offset - c.len_utf8()..self.position.offset);
                 }
             }
         }
-        if !paragraph.is_empty() {
-            elements.push(Element::Text(paragraph));
+        if let Some(start) = paragraph_start {
+            elements.push(Element::Text(paragraph.take(), self.span_from(start)));
         }
         Ok(elements)
     }
 
+    fn sees_delimiter(&self) -> bool {
+        let rest = self.rest_bytes();
+        self.delimiters.iter().any(|delimiter| rest.starts_with(delimiter.as_bytes()))
+    }
+
     fn sees_end_of_block(&self) -> bool {
-        for ext in self.extensions.iter() {
+        for ext in self.extensions.triggered_by(self.peek()) {
             if ext.sees_end_of_block(self) {
                 return true;
             }
         }
 
-        match (self.peek(), self.peek_at(self.offset + 1)) {
-            ('\n', '\n') | ('\n', '\0') | ('\0', '\0') => true,
-            _ => false,
+        matches!(
+            self.rest_bytes(),
+            [] | [b'\n'] | [b'\n', b'\n', ..] | [b'\n', 0, ..] | [0] | [0, 0, ..]
+        )
+    }
+}
+
+/// The text of an element as it is being read. It is borrowed from the source
+/// for as long as it reads the same, and only copied once whitespace has been
+/// collapsed or an escape removed.
+struct Text<'a> {
+    raw: &'a str,
+    borrowed: Range<usize>,
+    owned: Option<String>,
+}
+
+impl<'a> Text<'a> {
+    fn new(raw: &'a str) -> Text<'a> {
+        Text {
+            raw,
+            borrowed: 0..0,
+            owned: None,
+        }
+    }
+
+    /// Appends `text`, which stands for the source from `offset` onwards.
+    fn push(&mut self, offset: usize, text: &str) {
+        if self.owned.is_none() {
+            if self.borrowed.is_empty() {
+                self.borrowed = offset..offset;
+            }
+            if self.borrowed.end == offset && self.raw[offset..].starts_with(text) {
+                self.borrowed.end += text.len();
+                return;
+            }
+            self.owned = Some(self.raw[self.borrowed.clone()].into());
+        }
+        if let Some(owned) = self.owned.as_mut() {
+            owned.push_str(text);
+        }
+    }
+
+    /// Appends the source in `range` as it is.
+    fn push_source(&mut self, range: Range<usize>) {
+        match self.owned.as_mut() {
+            None if self.borrowed.is_empty() => self.borrowed = range,
+            None if self.borrowed.end == range.start => self.borrowed.end = range.end,
+            _ => self.push(range.start, &self.raw[range]),
+        }
+    }
+
+    fn take(&mut self) -> Cow<'a, str> {
+        let borrowed = std::mem::replace(&mut self.borrowed, 0..0);
+        match self.owned.take() {
+            Some(owned) => Cow::Owned(owned),
+            None => Cow::Borrowed(&self.raw[borrowed]),
         }
     }
 }
@@ -230,22 +826,33 @@ impl Parser {
 mod tests {
     use super::*;
 
+    fn span(start: (usize, usize, usize), end: (usize, usize, usize)) -> Span {
+        Span::new(
+            Position::new(start.0, start.1, start.2),
+            Position::new(end.0, end.1, end.2),
+        )
+    }
+
     #[test]
     fn empty_source() {
-        let parser = Parser::new("".into());
+        let parser = Parser::new("");
 
         assert_eq!(parser.parse().unwrap(), vec![]);
     }
 
     #[test]
     fn just_a_bit_of_text() {
-        let parser = Parser::new("Hello! This is a sentence!".into());
+        let parser = Parser::new("Hello! This is a sentence!");
 
         assert_eq!(
             parser.parse().unwrap(),
-            vec![Block::Paragraph(vec![Element::Text(
-                "Hello! This is a sentence!".into()
-            )])]
+            vec![Block::Paragraph(
+                vec![Element::Text(
+                    "Hello! This is a sentence!".into(),
+                    span((0, 1, 1), (26, 1, 27))
+                )],
+                span((0, 1, 1), (26, 1, 27))
+            )]
         );
     }
 
@@ -258,15 +865,18 @@ mod tests {
               This is a paragraph! # Which happens to include a comment
               And it spans multiple
               lines!
-            "#
-            .into(),
+            "#,
         );
 
         assert_eq!(
             parser.parse().unwrap(),
-            vec![Block::Paragraph(vec![Element::Text(
-                "This is a paragraph! And it spans multiple lines!".into()
-            )])]
+            vec![Block::Paragraph(
+                vec![Element::Text(
+                    "This is a paragraph! And it spans multiple lines!".into(),
+                    span((64, 4, 15), (178, 6, 21))
+                )],
+                span((64, 4, 15), (178, 6, 21))
+            )]
         );
     }
 
@@ -275,8 +885,7 @@ mod tests {
         let parser = Parser::new(
             r#"
               This isn't Markdown!
-            "#
-            .into(),
+            "#,
         );
 
         assert_eq!(
@@ -290,8 +899,7 @@ mod tests {
         let mut parser = Parser::new(
             r#"
               This *isn't* Markdown!
-            "#
-            .into(),
+            "#,
         );
 
         parser.add_extension(extensions::Bold);
@@ -307,8 +915,7 @@ mod tests {
         let mut parser = Parser::new(
             r#"
               This /isn't/ Markdown!
-            "#
-            .into(),
+            "#,
         );
 
         parser.add_extension(extensions::Italics);
@@ -328,8 +935,7 @@ mod tests {
 
               ** This is a different list.
               ** With two items!
-            "#
-            .into(),
+            "#,
         );
 
         parser.add_extension(extensions::Lists::new("**"));
@@ -337,28 +943,1534 @@ mod tests {
         assert_eq!(
             parser.parse().unwrap(),
             vec![
-                Block::Paragraph(vec![Element::Text("This is a paragraph.".into())]),
+                Block::Paragraph(
+                    vec![Element::Text(
+                        "This is a paragraph.".into(),
+                        span((15, 2, 15), (35, 2, 35))
+                    )],
+                    span((15, 2, 15), (35, 2, 35))
+                ),
                 Block::ExtensionBlocks(
                     extensions::LIST,
-                    vec![Block::ExtensionBlock(
+                    vec![Block::ExtensionBlocks(
                         extensions::LIST_ITEM,
-                        vec![Element::Text("This is a list item.".into())]
-                    )]
+                        vec![Block::Paragraph(
+                            vec![Element::Text(
+                                "This is a list item.".into(),
+                                span((53, 3, 18), (73, 3, 38))
+                            )],
+                            span((53, 3, 18), (73, 3, 38))
+                        )],
+                        vec![],
+                        span((50, 3, 15), (73, 3, 38))
+                    )],
+                    vec![],
+                    span((50, 3, 15), (73, 3, 38))
                 ),
                 Block::ExtensionBlocks(
                     extensions::LIST,
                     vec![
-                        Block::ExtensionBlock(
+                        Block::ExtensionBlocks(
                             extensions::LIST_ITEM,
-                            vec![Element::Text("This is a different list.".into())]
+                            vec![Block::Paragraph(
+                                vec![Element::Text(
+                                    "This is a different list.".into(),
+                                    span((92, 5, 18), (117, 5, 43))
+                                )],
+                                span((92, 5, 18), (117, 5, 43))
+                            )],
+                            vec![],
+                            span((89, 5, 15), (117, 5, 43))
                         ),
-                        Block::ExtensionBlock(
+                        Block::ExtensionBlocks(
                             extensions::LIST_ITEM,
-                            vec![Element::Text("With two items!".into())]
+                            vec![Block::Paragraph(
+                                vec![Element::Text(
+                                    "With two items!".into(),
+                                    span((135, 6, 18), (150, 6, 33))
+                                )],
+                                span((135, 6, 18), (150, 6, 33))
+                            )],
+                            vec![],
+                            span((132, 6, 15), (150, 6, 33))
                         ),
-                    ]
+                    ],
+                    vec![],
+                    span((89, 5, 15), (150, 6, 33))
                 ),
             ],
         );
     }
+
+    #[test]
+    fn nested_lists() {
+        let mut parser = Parser::new("- One\n  - Two\n    - Three\n  - Four\n- Five");
+
+        parser.add_extension(extensions::Lists::new("-"));
+
+        fn item<'a>(text: &'a str, span: Span, mut rest: Vec<Block<'a>>) -> Block<'a> {
+            let paragraph_start = Position::new(
+                span.start.offset + 2,
+                span.start.line,
+                span.start.column + 2,
+            );
+            let paragraph_end = Position::new(
+                paragraph_start.offset + text.len(),
+                span.start.line,
+                paragraph_start.column + text.len(),
+            );
+            let paragraph_span = Span::new(paragraph_start, paragraph_end);
+            rest.insert(
+                0,
+                Block::Paragraph(vec![Element::Text(text.into(), paragraph_span)], paragraph_span),
+            );
+            Block::ExtensionBlocks(extensions::LIST_ITEM, rest, vec![], span)
+        }
+
+        assert_eq!(
+            parser.parse().unwrap(),
+            vec![Block::ExtensionBlocks(
+                extensions::LIST,
+                vec![
+                    item(
+                        "One",
+                        span((0, 1, 1), (34, 4, 9)),
+                        vec![Block::ExtensionBlocks(
+                            extensions::LIST,
+                            vec![
+                                item(
+                                    "Two",
+                                    span((8, 2, 3), (25, 3, 12)),
+                                    vec![Block::ExtensionBlocks(
+                                        extensions::LIST,
+                                        vec![item("Three", span((18, 3, 5), (25, 3, 12)), vec![])],
+                                        vec![],
+                                        span((18, 3, 5), (25, 3, 12))
+                                    )]
+                                ),
+                                item("Four", span((28, 4, 3), (34, 4, 9)), vec![]),
+                            ],
+                            vec![],
+                            span((8, 2, 3), (34, 4, 9))
+                        )]
+                    ),
+                    item("Five", span((35, 5, 1), (41, 5, 7)), vec![]),
+                ],
+                vec![],
+                span((0, 1, 1), (41, 5, 7))
+            )]
+        );
+    }
+
+    #[test]
+    fn multi_paragraph_list_items() {
+        let mut parser = Parser::new(
+            r#"
+              - First item.
+
+                Still the first item.
+                - Nested.
+
+              - Second list.
+
+              Not in a list.
+            "#,
+        );
+
+        parser.add_extension(extensions::Lists::new("-"));
+
+        assert_eq!(
+            parser.emit_with(&HtmlEmitter::new()).unwrap(),
+            "<ul>\n  \
+               <li>\n    \
+                 <p>\n      First item.\n    </p>\n    \
+                 <p>\n      Still the first item.\n    </p>\n    \
+                 <ul>\n      \
+                   <li>\n        Nested.\n      </li>\n    \
+                 </ul>\n  \
+               </li>\n\
+             </ul>\n\
+             <ul>\n  \
+               <li>\n    Second list.\n  </li>\n\
+             </ul>\n\
+             <p>\n  Not in a list.\n</p>\n"
+        );
+    }
+
+    #[test]
+    fn bullets_only_count_at_the_start_of_a_line() {
+        let mut parser = Parser::new("Five - three = two");
+
+        parser.add_extension(extensions::Lists::new("-"));
+
+        assert_eq!(
+            parser.parse().unwrap(),
+            vec![Block::Paragraph(
+                vec![Element::Text("Five - three = two".into(), span((0, 1, 1), (18, 1, 19)))],
+                span((0, 1, 1), (18, 1, 19))
+            )]
+        );
+    }
+
+    #[test]
+    fn spans_of_inline_elements() {
+        let mut parser = Parser::new("Très *bold*\nand #note\n  /more/");
+
+        parser.add_extension(extensions::Bold);
+        parser.add_extension(extensions::Italics);
+
+        assert_eq!(
+            parser.parse().unwrap(),
+            vec![Block::Paragraph(
+                vec![
+                    Element::Text("Très ".into(), span((0, 1, 1), (6, 1, 6))),
+                    Element::ExtensionElement(
+                        extensions::BOLD,
+                        vec![Element::Text("bold".into(), span((7, 1, 7), (11, 1, 11)))],
+                        span((6, 1, 6), (12, 1, 12))
+                    ),
+                    Element::Text(" and ".into(), span((12, 1, 12), (25, 3, 3))),
+                    Element::ExtensionElement(
+                        extensions::ITALICS,
+                        vec![Element::Text("more".into(), span((26, 3, 4), (30, 3, 8)))],
+                        span((25, 3, 3), (31, 3, 9))
+                    ),
+                ],
+                span((0, 1, 1), (31, 3, 9))
+            )]
+        );
+    }
+
+    #[test]
+    fn recovering_from_errors() {
+        struct Picky;
+
+        impl Extension for Picky {
+            fn parse_element<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Element<'a>>> {
+                let start = parser.position();
+                match parser.peek() {
+                    '!' => {
+                        parser.take();
+                        Err(Box::new(Diagnostic::error(parser.span_from(start), "no shouting")))
+                    }
+                    '?' => {
+                        parser.take();
+                        parser.report(Diagnostic::warning(parser.span_from(start), "be assertive"))?;
+                        parser.report(Diagnostic::warning(parser.span_from(start), "or at least polite"))?;
+                        Ok(None)
+                    }
+                    _ => Ok(None),
+                }
+            }
+        }
+
+        let mut parser = Parser::new("First!\nStill first.\n\nSecond?\n\nThird.");
+        parser.add_extension(Picky);
+
+        let (blocks, diagnostics) = parser.parse_recovering();
+
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Paragraph(
+                    vec![Element::Text("Second?".into(), span((21, 4, 1), (28, 4, 8)))],
+                    span((21, 4, 1), (28, 4, 8))
+                ),
+                Block::Paragraph(
+                    vec![Element::Text("Third.".into(), span((30, 6, 1), (36, 6, 7)))],
+                    span((30, 6, 1), (36, 6, 7))
+                ),
+            ]
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::error(span((5, 1, 6), (6, 1, 7)), "no shouting"),
+                Diagnostic::warning(span((27, 4, 7), (28, 4, 8)), "be assertive"),
+                Diagnostic::warning(span((27, 4, 7), (28, 4, 8)), "or at least polite"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_delimiters_are_reported() {
+        let mut parser = Parser::new("This *isn't closed.\n\nNor /is this.");
+
+        parser.add_extension(extensions::Bold);
+        parser.add_extension(extensions::Italics);
+
+        let (blocks, diagnostics) = parser.parse_recovering();
+
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Paragraph(
+                    vec![Element::Text("This *isn't closed.".into(), span((0, 1, 1), (19, 1, 20)))],
+                    span((0, 1, 1), (19, 1, 20))
+                ),
+                Block::Paragraph(
+                    vec![Element::Text("Nor /is this.".into(), span((21, 3, 1), (34, 3, 14)))],
+                    span((21, 3, 1), (34, 3, 14))
+                ),
+            ]
+        );
+        assert_eq!(
+            diagnostics.iter().map(|d| (d.severity, d.code, d.span)).collect::<Vec<_>>(),
+            vec![
+                (Severity::Warning, Some(extensions::UNCLOSED_BOLD), span((5, 1, 6), (6, 1, 7))),
+                (Severity::Warning, Some(extensions::UNCLOSED_ITALICS), span((25, 3, 5), (26, 3, 6))),
+            ]
+        );
+        assert_eq!(diagnostics[0].expected, vec!["`*`"]);
+        assert_eq!(diagnostics[1].expected, vec!["`/`"]);
+    }
+
+    #[test]
+    fn unclosed_delimiters_can_be_denied() {
+        let mut parser = Parser::new("This *isn't closed.");
+
+        parser.add_extension(extensions::Bold);
+        parser.deny_warnings();
+
+        let error = parser.parse().unwrap_err();
+
+        assert_eq!(error.severity, Severity::Error);
+        assert_eq!(error.code, Some(extensions::UNCLOSED_BOLD));
+        assert_eq!(error.span, span((5, 1, 6), (6, 1, 7)));
+    }
+
+    #[test]
+    fn nested_inline_elements() {
+        let mut parser = Parser::new("Some *bold /and italic/* text.");
+
+        parser.add_extension(extensions::Bold);
+        parser.add_extension(extensions::Italics);
+
+        assert_eq!(
+            parser.parse().unwrap(),
+            vec![Block::Paragraph(
+                vec![
+                    Element::Text("Some ".into(), span((0, 1, 1), (5, 1, 6))),
+                    Element::ExtensionElement(
+                        extensions::BOLD,
+                        vec![
+                            Element::Text("bold ".into(), span((6, 1, 7), (11, 1, 12))),
+                            Element::ExtensionElement(
+                                extensions::ITALICS,
+                                vec![Element::Text("and italic".into(), span((12, 1, 13), (22, 1, 23)))],
+                                span((11, 1, 12), (23, 1, 24))
+                            ),
+                        ],
+                        span((5, 1, 6), (24, 1, 25))
+                    ),
+                    Element::Text(" text.".into(), span((24, 1, 25), (30, 1, 31))),
+                ],
+                span((0, 1, 1), (30, 1, 31))
+            )]
+        );
+    }
+
+    #[test]
+    fn nested_inline_elements_are_emitted() {
+        let source = "Some *bold /and italic/* text.";
+
+        let mut parser = Parser::new(source);
+        parser.add_extension(extensions::Bold);
+        parser.add_extension(extensions::Italics);
+        assert_eq!(
+            parser.emit_with(&HtmlEmitter::new()).unwrap(),
+            "<p>\n  Some <strong>bold <em>and italic</em></strong> text.\n</p>\n"
+        );
+
+        let mut parser = Parser::new(source);
+        parser.add_extension(extensions::Bold);
+        parser.add_extension(extensions::Italics);
+        assert_eq!(
+            parser.emit_with(&TextEmitter::new()).unwrap(),
+            "Some bold and italic text.\n"
+        );
+    }
+
+    #[test]
+    fn inner_elements_cannot_outlive_outer_ones() {
+        let mut parser = Parser::new("*bold /italic* rest/");
+
+        parser.add_extension(extensions::Bold);
+        parser.add_extension(extensions::Italics);
+
+        let (blocks, diagnostics) = parser.parse_recovering();
+
+        assert_eq!(
+            blocks,
+            vec![Block::Paragraph(
+                vec![
+                    Element::ExtensionElement(
+                        extensions::BOLD,
+                        vec![Element::Text("bold /italic".into(), span((1, 1, 2), (13, 1, 14)))],
+                        span((0, 1, 1), (14, 1, 15))
+                    ),
+                    Element::Text(" rest/".into(), span((14, 1, 15), (20, 1, 21))),
+                ],
+                span((0, 1, 1), (20, 1, 21))
+            )]
+        );
+        assert_eq!(
+            diagnostics.iter().map(|d| (d.code, d.span)).collect::<Vec<_>>(),
+            vec![
+                (Some(extensions::UNCLOSED_ITALICS), span((6, 1, 7), (7, 1, 8))),
+                (Some(extensions::UNCLOSED_ITALICS), span((19, 1, 20), (20, 1, 21))),
+            ]
+        );
+    }
+
+    #[test]
+    fn ordered_lists_extension() {
+        let mut parser = Parser::new("3) Three\n4) Four\n   a. Nested\n   b. Lists\n\ni. Roman");
+
+        parser.add_extension(extensions::OrderedLists);
+
+        let blocks = parser.parse().unwrap();
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].attribute("start"), Some("3"));
+        assert_eq!(blocks[0].attribute("type"), Some("1"));
+        assert_eq!(blocks[0].attribute("delimiter"), Some(")"));
+        assert_eq!(blocks[1].attribute("start"), Some("1"));
+        assert_eq!(blocks[1].attribute("type"), Some("i"));
+        assert_eq!(blocks[1].attribute("delimiter"), Some("."));
+
+        match &blocks[0] {
+            Block::ExtensionBlocks(extensions::ORDERED_LIST, items, _, _) => {
+                assert_eq!(items.len(), 2);
+                match &items[1] {
+                    Block::ExtensionBlocks(extensions::LIST_ITEM, blocks, _, _) => {
+                        assert_eq!(blocks[1].attribute("type"), Some("a"));
+                    }
+                    block => panic!("unexpected {:?}", block),
+                }
+            }
+            block => panic!("unexpected {:?}", block),
+        }
+    }
+
+    #[test]
+    fn ordered_lists_only_interrupt_paragraphs_at_one() {
+        let parse = |source: &str| -> Vec<Block<'static>> {
+            let mut parser = Parser::new(source);
+            parser.add_extension(extensions::OrderedLists);
+            parser.parse().unwrap().into_iter().map(Block::into_owned).collect()
+        };
+        let items = |block: &Block| match block {
+            Block::ExtensionBlocks(extensions::ORDERED_LIST, items, _, _) => items.len(),
+            block => panic!("unexpected {:?}", block),
+        };
+
+        for prose in &[
+            "The year was\n2001. It was cold.",
+            "Signed by\nA. Smith",
+            "We met\nI. Newton there.",
+            "12345678901.x",
+        ] {
+            let blocks = parse(prose);
+            assert!(matches!(blocks[..], [Block::Paragraph(..)]), "{:?} parsed as {:?}", prose, blocks);
+        }
+
+        let blocks = parse("Steps:\ni. Mix\nii. Bake");
+        assert!(matches!(blocks[0], Block::Paragraph(..)));
+        assert_eq!(items(&blocks[1]), 2);
+
+        // The next item of a list still ends the paragraph before it, but a
+        // line that couldn't continue the list doesn't.
+        let blocks = parse("a. The year was\n2001. It was cold.\nb. Next");
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(items(&blocks[0]), 2);
+
+        // After a blank line, any number starts a list.
+        let blocks = parse("The year was\n\n2001. It was cold.");
+        assert_eq!(blocks[1].attribute("start"), Some("2001"));
+    }
+
+    #[test]
+    fn alpha_lists_continue_past_z() {
+        let parse = |source: &str| -> Vec<Block<'static>> {
+            let mut parser = Parser::new(source);
+            parser.add_extension(extensions::OrderedLists);
+            let mut blocks: Vec<_> = parser.parse().unwrap().into_iter().map(Block::into_owned).collect();
+            blocks.iter_mut().for_each(|block| block.map_positions(&|_| Position::default()));
+            blocks
+        };
+
+        let blocks = parse("z. a\nb. b\n");
+        let mut parser = Parser::new("");
+        parser.add_extension(extensions::OrderedLists);
+        let printed = parser.publication_emitter().print_document(&blocks, &Trivia::default());
+        assert_eq!(printed, "z. a\naa. b\n");
+        assert_eq!(parse(&printed), blocks);
+
+        let blocks = parse("Y) One\nZ) Two\nAA) Three\nAB) Four\n");
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].attribute("start"), Some("25"));
+
+        // A marker that could only continue a list starts a nested one too,
+        // rather than an empty paragraph.
+        let blocks = parse("y.\n   ab. x\n");
+        assert_eq!(blocks.len(), 1);
+        match &blocks[0] {
+            Block::ExtensionBlocks(_, items, _, _) => match &items[0] {
+                Block::ExtensionBlocks(_, blocks, _, _) => assert_eq!(blocks[0].attribute("start"), Some("28")),
+                block => panic!("unexpected {:?}", block),
+            },
+            block => panic!("unexpected {:?}", block),
+        }
+    }
+
+    #[test]
+    fn ordered_lists_are_emitted() {
+        let source = "9. Nine\n10. Ten\n    i) Nested\n    ii) Roman\n\nc. Letters";
+
+        let mut parser = Parser::new(source);
+        parser.add_extension(extensions::OrderedLists);
+        assert_eq!(
+            parser.emit_with(&HtmlEmitter::new()).unwrap(),
+            "<ol start=\"9\">\n  \
+               <li>\n    Nine\n  </li>\n  \
+               <li>\n    Ten\n    \
+                 <ol type=\"i\">\n      \
+                   <li>\n        Nested\n      </li>\n      \
+                   <li>\n        Roman\n      </li>\n    \
+                 </ol>\n  \
+               </li>\n\
+             </ol>\n\
+             <ol start=\"3\" type=\"a\">\n  \
+               <li>\n    Letters\n  </li>\n\
+             </ol>\n"
+        );
+
+        let mut parser = Parser::new(source);
+        parser.add_extension(extensions::OrderedLists);
+        assert_eq!(
+            parser.emit_with(&TextEmitter::new()).unwrap(),
+            " 9. Nine\n\
+             10. Ten\n     \
+                  i) Nested\n    \
+                 ii) Roman\n\
+             \n\
+             c. Letters\n"
+        );
+    }
+
+    #[test]
+    fn numbering_styles() {
+        use extensions::Numbering;
+
+        assert_eq!(Numbering::Decimal.format(42), "42");
+        assert_eq!(Numbering::LowerAlpha.format(28), "ab");
+        assert_eq!(Numbering::UpperAlpha.format(26), "Z");
+        assert_eq!(Numbering::LowerRoman.format(1994), "mcmxciv");
+        assert_eq!(Numbering::UpperRoman.format(4), "IV");
+    }
+
+    #[test]
+    fn text_emitter_renders_lists() {
+        let mut parser = Parser::new(
+            r#"
+              Groceries:
+
+              - Fruit
+                - Apples
+                - Pears
+
+                Whatever is in season.
+              - Bread
+            "#,
+        );
+
+        parser.add_extension(extensions::Lists::new("-"));
+
+        assert_eq!(
+            parser.emit_with(&TextEmitter::new()).unwrap(),
+            "Groceries:\n\
+             \n\
+             • Fruit\n  \
+               • Apples\n  \
+               • Pears\n\
+             \n  \
+               Whatever is in season.\n\
+             • Bread\n"
+        );
+    }
+
+    #[test]
+    fn text_emitter_keeps_text_of_unknown_blocks() {
+        const QUOTE: ExtensionTag = ExtensionTag::from_static("MY_QUOTE");
+        const NOTE: ExtensionTag = ExtensionTag::from_static("MY_NOTE");
+
+        let emitter = TextEmitter::new();
+        let mut out = String::new();
+        emitter.emit_block(
+            Block::ExtensionBlocks(
+                QUOTE,
+                vec![
+                    Block::ExtensionBlock(NOTE, vec![Element::Text("Quoted.".into(), Span::default())], Span::default()),
+                    Block::Paragraph(vec![Element::Text("Also quoted.".into(), Span::default())], Span::default()),
+                ],
+                vec![],
+                Span::default(),
+            ),
+            &mut out,
+        );
+
+        assert_eq!(out, "Quoted.\n\nAlso quoted.\n");
+    }
+
+    #[test]
+    fn text_emitter_wraps_paragraphs_and_lists() {
+        let mut parser = Parser::new(
+            r#"
+              The quick brown fox jumps over the lazy dog.
+
+              - A list item that is long enough to be wrapped.
+                - And a nested one.
+            "#,
+        );
+
+        parser.add_extension(extensions::Lists::new("-"));
+
+        let mut emitter = TextEmitter::new();
+        emitter.wrap_at(20);
+
+        assert_eq!(
+            parser.emit_with(&emitter).unwrap(),
+            "The quick brown fox\n\
+             jumps over the lazy\n\
+             dog.\n\
+             \n\
+             • A list item that\n  \
+               is long enough to\n  \
+               be wrapped.\n  \
+               • And a nested\n    \
+                 one.\n"
+        );
+    }
+
+    #[test]
+    fn text_emitter_justifies_paragraphs() {
+        let parser = Parser::new("The quick brown fox jumps over the lazy dog.");
+
+        let mut emitter = TextEmitter::new();
+        emitter.wrap_at(16);
+        emitter.justify();
+
+        assert_eq!(
+            parser.emit_with(&emitter).unwrap(),
+            "The  quick brown\n\
+             fox  jumps  over\n\
+             the lazy dog.\n"
+        );
+    }
+
+    #[test]
+    fn text_emitter_wraps_by_display_width() {
+        let parser = Parser::new("日本語のテキストです e\u{301}te\u{301} abcdefghijkl");
+
+        let mut emitter = TextEmitter::new();
+        emitter.wrap_at(8);
+
+        assert_eq!(
+            parser.emit_with(&emitter).unwrap(),
+            "日本語の\n\
+             テキスト\n\
+             です e\u{301}te\u{301}\n\
+             abcdefgh\n\
+             ijkl\n"
+        );
+    }
+
+    #[test]
+    fn markdown_emitter() {
+        let mut parser = Parser::new(
+            r#"
+              Some *bold /and italic/* text_with [special] <chars>.
+
+              - One
+                - Two
+
+                More about one.
+              - Three
+
+              1. Not a list, since there is no extension for it.
+
+              b) Second
+              c) Third
+            "#,
+        );
+
+        parser.add_extension(extensions::Bold);
+        parser.add_extension(extensions::Italics);
+        parser.add_extension(extensions::Lists::new("-"));
+
+        let (markdown, _) = parser.emit_recovering(&MarkdownEmitter::new());
+
+        assert_eq!(
+            markdown,
+            "Some **bold *and italic*** text\\_with \\[special\\] \\<chars\\>.\n\
+             \n\
+             - One\n  \
+               - Two\n\
+             \n  \
+               More about one.\n\
+             - Three\n\
+             \n\
+             1\\. Not a list, since there is no extension for it.\n\
+             \n\
+             b) Second c) Third\n"
+        );
+
+        let mut parser = Parser::new("b) Second\nc) Third");
+        parser.add_extension(extensions::OrderedLists);
+        assert_eq!(
+            parser.emit_with(&MarkdownEmitter::new()).unwrap(),
+            "2) Second\n3) Third\n"
+        );
+    }
+
+    #[test]
+    fn markdown_emitter_hooks() {
+        const QUOTE: ExtensionTag = ExtensionTag::from_static("MY_QUOTE");
+
+        let mut emitter = MarkdownEmitter::new();
+        emitter.tagged_blocks(QUOTE, |_| TextFormat::new().with_indent("> "));
+
+        let mut out = String::new();
+        emitter.emit_block(
+            Block::ExtensionBlocks(
+                QUOTE,
+                vec![
+                    Block::Paragraph(vec![Element::Text("Quoted.".into(), Span::default())], Span::default()),
+                    Block::Paragraph(vec![Element::Text("Also quoted.".into(), Span::default())], Span::default()),
+                ],
+                vec![],
+                Span::default(),
+            ),
+            &mut out,
+        );
+
+        assert_eq!(out, "> Quoted.\n\n> Also quoted.\n");
+    }
+
+    #[test]
+    fn markdown_import() {
+        let mut importer = MarkdownImporter::new();
+        importer.bold();
+        importer.lists("-");
+
+        let import = importer.import(
+            "# Title\n\
+             \n\
+             Some **bold** and *emphasized* text.\n\
+             \n\
+             - One\n\
+             - Two\n  \
+             - Nested\n\
+             \n\
+             1. First\n",
+        );
+
+        assert_eq!(
+            import.source,
+            "Title\n\
+             \n\
+             Some *bold* and emphasized text.\n\
+             \n\
+             - One\n\
+             - Two\n  \
+             - Nested\n\
+             \n\
+             First\n"
+        );
+        assert_eq!(
+            import.losses,
+            vec![
+                Loss {
+                    construct: "heading",
+                    line: 1,
+                    extension: None,
+                },
+                Loss {
+                    construct: "emphasis",
+                    line: 3,
+                    extension: Some("Italics"),
+                },
+                Loss {
+                    construct: "list",
+                    line: 9,
+                    extension: Some("OrderedLists"),
+                },
+            ]
+        );
+
+        let import = importer.import("Issue #42: 2\\*3 and 4/2.\n");
+        assert_eq!(import.source, "Issue \\#42: 2\\*3 and 4/2.\n");
+        assert_eq!(import.losses, vec![]);
+
+        // Lines that would start a list stay in their paragraph.
+        let import = importer.import("Not\n\\- a list\n\n    - nor\n    - this\n");
+        assert_eq!(import.source, "Not - a list\n\n\\- nor - this\n");
+    }
+
+    #[test]
+    fn markdown_import_parses_back() {
+        let mut importer = MarkdownImporter::new();
+        importer.bold();
+        importer.italics();
+        importer.lists("*");
+        importer.ordered_lists();
+
+        let import = importer.import("Text with **bold _and italics_**.\n\n3. Three\n4. Four\n   * Nested\n");
+        assert_eq!(import.losses, vec![]);
+
+        let mut parser = Parser::new(&import.source);
+        parser.add_extension(extensions::Bold);
+        parser.add_extension(extensions::Italics);
+        parser.add_extension(extensions::Lists::new("*"));
+        parser.add_extension(extensions::OrderedLists);
+
+        assert_eq!(
+            parser.emit_with(&HtmlEmitter::new()).unwrap(),
+            "<p>\n  \
+               Text with <strong>bold <em>and italics</em></strong>.\n\
+             </p>\n\
+             <ol start=\"3\">\n  \
+               <li>\n    \
+                 Three\n  \
+               </li>\n  \
+               <li>\n    \
+                 Four\n    \
+                 <ul>\n      \
+                   <li>\n        \
+                     Nested\n      \
+                   </li>\n    \
+                 </ul>\n  \
+               </li>\n\
+             </ol>\n"
+        );
+    }
+
+    #[test]
+    fn publication_emitter() {
+        fn parser(source: &str) -> Parser<'_> {
+            let mut parser = Parser::new(source);
+            parser.add_extension(extensions::Bold);
+            parser.add_extension(extensions::Italics);
+            parser.add_extension(extensions::Lists::new("-"));
+            parser.add_extension(extensions::OrderedLists);
+            parser
+        }
+
+        let source = "# A comment\n\
+                      Some   *bold /and\n\
+                      italic/* text.\n\
+                      \n\
+                      - One\n  \
+                        - Nested\n\
+                      - Two\n\
+                      \n    \
+                        More about two.\n\
+                      -\n  \
+                        iv) Four\n  \
+                        v) Five\n\
+                      \n\
+                      b. Bee\n";
+
+        let printed = {
+            let parser = parser(source);
+            let emitter = parser.publication_emitter();
+            parser.emit_with(&emitter).unwrap()
+        };
+
+        assert_eq!(
+            printed,
+            "Some *bold /and italic/* text.\n\
+             \n\
+             - One\n  \
+               - Nested\n\
+             - Two\n\
+             \n  \
+               More about two.\n\
+             -\n  \
+               iv) Four\n  \
+               v) Five\n\
+             \n\
+             b. Bee\n"
+        );
+
+        let html = |source: &str| parser(source).emit_with(&HtmlEmitter::new()).unwrap();
+        assert_eq!(html(&printed), html(source));
+
+        let reprinted = {
+            let parser = parser(&printed);
+            let emitter = parser.publication_emitter();
+            parser.emit_with(&emitter).unwrap()
+        };
+        assert_eq!(reprinted, printed);
+
+        // Parsing the output gives back the same blocks, spans aside.
+        let blocks = |source: &str| -> Vec<Block<'static>> {
+            let mut blocks = parser(source).parse().unwrap();
+            blocks.iter_mut().for_each(|block| block.map_positions(&|_| Position::default()));
+            blocks.into_iter().map(Block::into_owned).collect()
+        };
+        for source in &[
+            source,
+            "  - \n \n-  x ya",
+            "-\n\n- b",
+            "- a\n    - b\n   - c",
+            "1. a\n   - b\n\n   - c",
+            "- b\n\n  ii) 1.",
+        ] {
+            let parsed = parser(source).parse().unwrap();
+            let printed = parser("").publication_emitter().print_document(&parsed, &Trivia::default());
+            assert_eq!(blocks(&printed), blocks(source), "{:?} printed as {:?}", source, printed);
+        }
+    }
+
+    #[test]
+    fn publication_emitter_wraps_and_keeps_comments() {
+        let source = "# Leading comment\n\
+                      A paragraph that is wrapped - twice, but # inline\n\
+                      never before the dash. # trailing\n\
+                      \n\
+                      - An item that wraps inside of the list\n  \
+                        # about the nested list\n  \
+                        - Nested\n\
+                      # closing comment\n";
+
+        let mut parser = Parser::new(source);
+        parser.add_extension(extensions::Lists::new("-"));
+        let mut emitter = parser.publication_emitter();
+        emitter.wrap_at(28);
+
+        let (blocks, trivia) = parser.parse_lossless().unwrap();
+        let printed = emitter.print_document(&blocks, &trivia);
+        assert_eq!(
+            printed,
+            "# Leading comment\n\
+             # inline\n\
+             A paragraph that is wrapped -\n\
+             twice, but never before the\n\
+             dash. # trailing\n\
+             \n\
+             - An item that wraps inside\n  \
+               of the list\n  \
+               # about the nested list\n  \
+               - Nested\n\
+             \n\
+             # closing comment\n"
+        );
+        // Printing leaves the comments in place for the next time.
+        assert_eq!(emitter.print_document(&blocks, &trivia), printed);
+
+        // Control characters in text are kept as they are, never wrapped at.
+        let blocks = Parser::new("a\u{1}b c\u{1}").parse().unwrap();
+        assert_eq!(emitter.print_document(&blocks, &Trivia::default()), "a\u{1}b c\u{1}\n");
+        emitter.wrap_at(3);
+        assert_eq!(emitter.print_document(&blocks, &Trivia::default()), "a\u{1}b\nc\u{1}\n");
+    }
+
+    #[test]
+    fn publication_emitter_round_trips() {
+        let sources = [
+            // Empty items, and sub-lists next to each other.
+            "-\n\n- b\n",
+            "  - \n \n-  x ya\n",
+            "- a\n    - b\n   - c\n",
+            "-\n  iv) Four\n  v) Five\n",
+            "1. a\n   - b\n\n   - c\n",
+            "- b\n\n  ii) 1.\n",
+            // Lists past the end of the alphabet.
+            "z. a\nb. b\n",
+            "y.\n   ab. x\n",
+            // Text that would be read as something else if it wasn't escaped.
+            "\\- not a list\n",
+            "The year was 2001\\. I. Newton a\\. b\\. c\\. 1\\. - x\n",
+            "\\*not bold\\* and \\/not italic\\/ \\\\ \\# a b \u{1} c\n",
+            // Comments around and inside of blocks.
+            "# leading\nSome *bold /and italic/ text* # trailing\n\n- item # trailing\n  # leading\n  - sub\n",
+            "#[ a\nblock ]# comment\n\n1. a #[ inline ]# b\n",
+            "- item #[ a\nb ]#\n- next\n",
+            "- item\n  #[ lead\n  more ]#\n  - sub\n",
+            "- a long item that wraps #[ and\n      a comment\n    after it ]#\n",
+        ];
+
+        let mut dialect = Dialect::new();
+        dialect.add_extension(extensions::Bold);
+        dialect.add_extension(extensions::Italics);
+        dialect.add_extension(extensions::Lists::new("-"));
+        dialect.add_extension(extensions::OrderedLists);
+        dialect.comment_syntax(CommentSyntax::default().with_block("#[", "]#"));
+        // The text of every comment, in the order they are printed in.
+        fn comments(blocks: &[Block], trivia: &Trivia) -> Vec<String> {
+            fn visit(blocks: &[Block], trivia: &Trivia, texts: &mut Vec<String>) {
+                for block in blocks {
+                    match block {
+                        Block::ExtensionBlocks(_, blocks, _, _) => visit(blocks, trivia, texts),
+                        block => {
+                            let of = trivia.of(block).cloned().unwrap_or_default();
+                            texts.extend(of.leading.into_iter().chain(of.inline).chain(of.trailing).map(|c| c.text));
+                        }
+                    }
+                }
+            }
+            let mut texts = vec![];
+            visit(blocks, trivia, &mut texts);
+            texts.extend(trivia.end.iter().map(|comment| comment.text.clone()));
+            texts
+        }
+        let without_spans = |blocks: &[Block]| {
+            let mut blocks: Vec<_> = blocks.iter().cloned().map(Block::into_owned).collect();
+            blocks.iter_mut().for_each(|block| block.map_positions(&|_| Position::default()));
+            blocks
+        };
+
+        for source in &sources {
+            let (blocks, trivia) = dialect.parse_lossless(source).unwrap();
+            for width in &[None, Some(1), Some(8), Some(20)] {
+                let mut emitter = dialect.publication_emitter();
+                if let Some(width) = width {
+                    emitter.wrap_at(*width);
+                }
+                let printed = emitter.print_document(&blocks, &trivia);
+                let (again, trivia_again) = dialect.parse_lossless(&printed).unwrap();
+                let message = format!("{:?} printed at {:?} as {:?}", source, width, printed);
+                assert_eq!(without_spans(&again), without_spans(&blocks), "{}", message);
+                assert_eq!(comments(&again, &trivia_again), comments(&blocks, &trivia), "{}", message);
+                assert_eq!(emitter.print_document(&again, &trivia_again), printed, "{}", message);
+            }
+        }
+
+        // Block comments in list items keep their lines where they are.
+        let source = "- item #[ a\n       b ]#\n- next\n\n- item\n  #[ lead\n  more ]#\n  - sub\n";
+        let (blocks, trivia) = dialect.parse_lossless(source).unwrap();
+        assert_eq!(dialect.publication_emitter().print_document(&blocks, &trivia), source);
+    }
+
+    #[test]
+    fn lossless_parse_keeps_comments_as_trivia() {
+        let source = "# Leading\n\
+                      First # trailing\n\
+                      \n\
+                      Second # inline\n\
+                      paragraph.\n\
+                      # end";
+
+        let (blocks, trivia) = Parser::new(source).parse_lossless().unwrap();
+
+        assert_eq!(
+            trivia.of(&blocks[0]),
+            Some(&BlockTrivia {
+                leading: vec![Comment {
+                    text: "# Leading".into(),
+                    span: span((0, 1, 1), (9, 1, 10)),
+                }],
+                inline: vec![],
+                trailing: vec![Comment {
+                    text: "# trailing".into(),
+                    span: span((16, 2, 7), (26, 2, 17)),
+                }],
+            })
+        );
+        assert_eq!(
+            trivia.of(&blocks[1]),
+            Some(&BlockTrivia {
+                leading: vec![],
+                inline: vec![Comment {
+                    text: "# inline".into(),
+                    span: span((35, 4, 8), (43, 4, 16)),
+                }],
+                trailing: vec![],
+            })
+        );
+        assert_eq!(
+            trivia.end,
+            vec![Comment {
+                text: "# end".into(),
+                span: span((55, 6, 1), (60, 6, 6)),
+            }]
+        );
+
+        // Emitters that don't know about trivia never see the comments.
+        assert_eq!(
+            Parser::new(source).emit_with(&HtmlEmitter::new()).unwrap(),
+            "<p>\n  First\n</p>\n<p>\n  Second paragraph.\n</p>\n"
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        fn parser(source: &str) -> Parser<'_> {
+            let mut parser = Parser::new(source);
+            parser.add_extension(extensions::Bold);
+            parser.add_extension(extensions::Lists::new("-"));
+            parser.add_extension(extensions::OrderedLists);
+            parser
+        }
+
+        let source = "\\- Issue \\#42 is *\\*really\\** fixed\n\
+                      in C:\\Windows\\\\.\n\
+                      \n\
+                      1\\. Not a list either.\n";
+
+        assert_eq!(
+            parser(source).parse().unwrap(),
+            vec![
+                Block::Paragraph(
+                    vec![
+                        Element::Text("- Issue #42 is ".into(), span((0, 1, 1), (17, 1, 18))),
+                        Element::ExtensionElement(
+                            extensions::BOLD,
+                            vec![Element::Text("*really*".into(), span((18, 1, 19), (28, 1, 29)))],
+                            span((17, 1, 18), (29, 1, 30)),
+                        ),
+                        Element::Text(" fixed in C:\\Windows\\.".into(), span((29, 1, 30), (52, 2, 17))),
+                    ],
+                    span((0, 1, 1), (52, 2, 17)),
+                ),
+                Block::Paragraph(
+                    vec![Element::Text("1. Not a list either.".into(), span((54, 4, 1), (76, 4, 23)))],
+                    span((54, 4, 1), (76, 4, 23)),
+                ),
+            ]
+        );
+
+        let printed = {
+            let parser = parser(source);
+            let emitter = parser.publication_emitter();
+            parser.emit_with(&emitter).unwrap()
+        };
+        assert_eq!(
+            printed,
+            "\\- Issue \\#42 is *\\*really\\** fixed in C:\\Windows\\\\.\n\
+             \n\
+             1\\. Not a list either.\n"
+        );
+        assert_eq!(parser(printed.as_ref()).parse().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn configurable_comment_syntax() {
+        let source = "C# and #tags #[ a block\n\
+                      \n\
+                      comment ]# stay. // But not this.\n\
+                      See https:\\//example.com/#top\n";
+
+        let mut parser = Parser::new(source);
+        parser.comment_syntax(CommentSyntax::line("//").with_block("#[", "]#"));
+        let emitter = parser.publication_emitter();
+        let (blocks, trivia) = parser.parse_lossless().unwrap();
+
+        assert_eq!(
+            blocks,
+            vec![Block::Paragraph(
+                vec![Element::Text(
+                    "C# and #tags stay. See https://example.com/#top".into(),
+                    span((0, 1, 1), (88, 4, 30)),
+                )],
+                span((0, 1, 1), (88, 4, 30)),
+            )]
+        );
+        assert_eq!(
+            trivia.of(&blocks[0]).unwrap().inline,
+            vec![
+                Comment {
+                    text: "#[ a block\n\ncomment ]#".into(),
+                    span: span((13, 1, 14), (35, 3, 11)),
+                },
+                Comment {
+                    text: "// But not this.".into(),
+                    span: span((42, 3, 18), (58, 3, 34)),
+                },
+            ]
+        );
+
+        let mut out = Printout::new();
+        emitter.print_blocks(&blocks, &mut out);
+        assert_eq!(out.as_str(), "C# and #tags stay. See https:\\//example.com/#top");
+
+        // Delimiters that make up a comment marker at the start of a block
+        // are escaped, rather than turning the rest of the line into one.
+        let mut parser = Parser::new("");
+        parser.add_extension(extensions::Italics);
+        parser.comment_syntax(CommentSyntax::line("//"));
+        let emitter = parser.publication_emitter();
+        let blocks = vec![Block::Paragraph(
+            vec![
+                Element::ExtensionElement(extensions::ITALICS, vec![], Span::default()),
+                Element::Text(" a /b/".into(), Span::default()),
+            ],
+            Span::default(),
+        )];
+        let printed = emitter.print_document(&blocks, &Trivia::default());
+        assert_eq!(printed, "\\// a \\/b\\/\n");
+        let mut parser = Parser::new(&printed);
+        parser.add_extension(extensions::Italics);
+        parser.comment_syntax(CommentSyntax::line("//"));
+        let reparsed = parser.parse_recovering().0;
+        assert!(matches!(&reparsed[..], [Block::Paragraph(..)]), "{:?}", reparsed);
+
+        let mut parser = Parser::new("Not a # comment.");
+        parser.comment_syntax(CommentSyntax::none());
+        assert_eq!(
+            parser.emit_with(&TextEmitter::new()).unwrap(),
+            "Not a # comment.\n"
+        );
+    }
+
+    #[test]
+    fn runtime_extension_tags() {
+        // An extension configured at runtime, like one loaded from a plugin.
+        struct Marker {
+            tag: ExtensionTag,
+            delimiter: char,
+        }
+
+        impl Extension for Marker {
+            fn parse_element<'a>(&self, parser: &mut Parser<'a>) -> ParseResult<Option<Element<'a>>> {
+                if parser.peek() != self.delimiter {
+                    return Ok(None);
+                }
+                let start = parser.position();
+                parser.take();
+                let delimiter = self.delimiter.to_string();
+                Ok(parser
+                    .parse_elements_until(delimiter.as_ref())?
+                    .map(|children| Element::ExtensionElement(self.tag, children, parser.span_from(start))))
+            }
+        }
+
+        let name = format!("{}:{}", "plugin", "HIGHLIGHT");
+        let tag = ExtensionTag::new(name.as_ref());
+        assert_eq!(tag, ExtensionTag::new("plugin:HIGHLIGHT"));
+        assert_eq!(tag.name(), "plugin:HIGHLIGHT");
+        assert_eq!(ExtensionTag::new(extensions::BOLD.name()), extensions::BOLD);
+        assert!(std::ptr::eq(tag.name(), ExtensionTag::new("plugin:HIGHLIGHT").name()));
+        assert!(matches!(ExtensionTag::new("builtin:LIST"), extensions::LIST));
+        assert_eq!(DiagnosticCode::new("builtin:UNCLOSED_BOLD"), extensions::UNCLOSED_BOLD);
+
+        const HIGHLIGHT: ExtensionTag = ExtensionTag::from_static("plugin:HIGHLIGHT");
+        assert_eq!(HIGHLIGHT, tag);
+
+        let mut parser = Parser::new("Some =marked= text");
+        parser.add_extension(Marker { tag, delimiter: '=' });
+
+        let mut emitter = HtmlEmitter::new();
+        emitter.tagged_element(ExtensionTag::new("plugin:HIGHLIGHT"), |_| ("mark".into(), vec![]));
+
+        assert_eq!(
+            parser.emit_with(&emitter).unwrap(),
+            "<p>\n  Some <mark>marked</mark> text\n</p>\n"
+        );
+    }
+
+    #[test]
+    fn text_is_borrowed_from_the_source() {
+        let mut parser = Parser::new("Plain *bold* text\nover   lines, and \\*escaped\\*.");
+        parser.add_extension(extensions::Bold);
+        let blocks = parser.parse().unwrap();
+
+        let texts: Vec<&Cow<str>> = match &blocks[0] {
+            Block::Paragraph(elements, _) => elements
+                .iter()
+                .filter_map(|element| match element {
+                    Element::Text(text, _) => Some(text),
+                    Element::ExtensionElement(_, children, _) => match &children[0] {
+                        Element::Text(text, _) => Some(text),
+                        _ => None,
+                    },
+                })
+                .collect(),
+            _ => panic!("expected a paragraph"),
+        };
+
+        assert_eq!(texts, vec!["Plain ", "bold", " text over lines, and *escaped*."]);
+        assert!(matches!(texts[0], Cow::Borrowed(_)));
+        assert!(matches!(texts[1], Cow::Borrowed(_)));
+        assert!(matches!(texts[2], Cow::Owned(_)));
+
+        let owned: Vec<Block<'static>> = blocks.iter().cloned().map(Block::into_owned).collect();
+        assert_eq!(owned, blocks);
+    }
+
+    #[test]
+    fn streaming_emit() {
+        let source = "A paragraph.\n\n- A *list*\n  - Nested\n\n1. One\n2. Two\n\nThe end.";
+        let mut dialect = Dialect::new();
+        dialect.add_extension(extensions::Bold);
+        dialect.add_extension(extensions::Lists::new("-"));
+        dialect.add_extension(extensions::OrderedLists);
+
+        let emitters: Vec<Box<dyn Emitter>> = vec![
+            Box::new(HtmlEmitter::new()),
+            Box::new(TextEmitter::new()),
+            Box::new(MarkdownEmitter::new()),
+            Box::new(dialect.publication_emitter()),
+        ];
+        for emitter in emitters.iter() {
+            let expected = dialect.emit(source, emitter.as_ref()).unwrap();
+
+            let mut written = vec![];
+            dialect.emit_to(source, emitter.as_ref(), &mut written).unwrap();
+            assert_eq!(String::from_utf8(written).unwrap(), expected);
+
+            let mut formatted = String::new();
+            dialect.parser(source).emit_to_fmt(emitter.as_ref(), &mut formatted).unwrap();
+            assert_eq!(formatted, expected);
+        }
+
+        struct Full;
+
+        impl io::Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("full"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        match dialect.emit_to(source, &HtmlEmitter::new(), Full) {
+            Err(EmitError::Io(error)) => assert_eq!(error.to_string(), "full"),
+            result => panic!("expected a write error, got {:?}", result),
+        }
+
+        let mut strict = dialect.clone();
+        strict.deny_warnings();
+        match strict.emit_to(source, &HtmlEmitter::new(), io::sink()) {
+            Ok(()) => {}
+            result => panic!("expected no error, got {:?}", result),
+        }
+        match strict.emit_to("An *unclosed bold", &HtmlEmitter::new(), io::sink()) {
+            Err(EmitError::Parse(diagnostic)) => assert_eq!(diagnostic.code, Some(extensions::UNCLOSED_BOLD)),
+            result => panic!("expected a parse error, got {:?}", result),
+        }
+
+        let mut written = vec![];
+        let diagnostics = dialect
+            .parser("Fine.\n\nAn *unclosed bold.")
+            .emit_recovering_to(&HtmlEmitter::new(), &mut written)
+            .unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            dialect.parser("Fine.\n\nAn *unclosed bold.").emit_recovering(&HtmlEmitter::new()).0
+        );
+    }
+
+    #[test]
+    fn reading_blocks() {
+        // Hands out the source a few bytes at a time, to split it everywhere.
+        struct Trickle<'a>(&'a [u8], usize);
+
+        impl io::Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = self.1.min(self.0.len()).min(buf.len());
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        let source = "#[ A block comment\n\nover lines ]# Some *bold*\n\
+                      text, and ünïcode.\n\
+                      \n\
+                      - One\n  \
+                        - Nested\n\
+                      \n  \
+                        More about one. # A comment\n\
+                      - Two\n\
+                      \n\
+                      1. Numbered\n\
+                      \n\
+                      An *unclosed bold\n\
+                      \n\
+                      The end.";
+        let mut dialect = Dialect::new();
+        dialect.add_extension(extensions::Bold);
+        dialect.add_extension(extensions::Lists::new("-"));
+        dialect.add_extension(extensions::OrderedLists);
+
+        // Where the reads split a source must not change how it parses, like
+        // a list after a paragraph, whose items are nested by their columns.
+        let groceries = "Groceries:\n  - Fruit\n    - Apples\n  - Bread\n\nDone.\n";
+        for source in [source, groceries] {
+            let (expected, expected_diagnostics) = dialect.parse_recovering(source);
+            for step in 1..=source.len() {
+                let mut reader = dialect.read_blocks(Trickle(source.as_bytes(), step)).recovering();
+                let blocks: Vec<Block> = (&mut reader).map(Result::unwrap).collect();
+                assert_eq!(blocks, expected, "reading {:?} {} bytes at a time", source, step);
+                assert_eq!(reader.diagnostics(), expected_diagnostics.as_slice());
+            }
+        }
+
+        let mut strict = dialect.clone();
+        strict.deny_warnings();
+        let expected_error = strict.parse(source).unwrap_err();
+        let read: Vec<Result<Block, ReadError>> = strict.read_blocks(Trickle(source.as_bytes(), 3)).collect();
+        assert_eq!(read.len(), 4);
+        assert!(read[..3].iter().all(Result::is_ok));
+        match &read[3] {
+            Err(ReadError::Parse(error)) => assert_eq!(error.span, expected_error.span),
+            result => panic!("expected a parse error, got {:?}", result),
+        }
+
+        // An error is reported once the block after it has started, without
+        // reading the rest of the input first.
+        let long = format!("Fine.\n\nAn *unclosed bold\n\n{}", "More text.\n\n".repeat(100_000));
+        let mut input = io::Cursor::new(long.as_bytes());
+        let read: Vec<Result<Block, ReadError>> = strict.read_blocks(&mut input).collect();
+        assert_eq!(read.len(), 2);
+        assert!(matches!(read[1], Err(ReadError::Parse(_))));
+        assert!(input.position() < 64 * 1024);
+
+        match dialect.read_blocks(&b"Not \xff UTF-8"[..]).next() {
+            Some(Err(ReadError::Io(error))) => assert_eq!(error.kind(), io::ErrorKind::InvalidData),
+            result => panic!("expected a read error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn editing_documents() {
+        let mut dialect = Dialect::new();
+        dialect.add_extension(extensions::Bold);
+        dialect.add_extension(extensions::Lists::new("-"));
+        dialect.add_extension(extensions::OrderedLists);
+
+        let mut document = dialect.document(
+            "First.\n\
+             \n\
+             - One\n  \
+               - Nested\n\
+             - Two\n\
+             \n\
+             Thïrd, with *bold*. # A comment\n\
+             \n\
+             1. Four\n\
+             \n\
+             Fifth.",
+        );
+        assert_eq!(document.blocks().len(), 5);
+
+        let edits: &[(&str, &str)] = &[
+            // Typing in the middle only reparses around the edit.
+            ("Thïrd", "Third and a half"),
+            // Unclosed bold, then closing it again.
+            ("*bold*", "*bold"),
+            ("*bold", "*bold*"),
+            // Joining and splitting paragraphs.
+            ("First.\n\n", "First.\n"),
+            ("First.\n", "First.\n\n\n"),
+            // Continuing a list.
+            ("- Two\n", "- Two\n- Two and a half\n"),
+            // Commenting out everything after, then closing the comment.
+            ("# A comment", "#[ A comment"),
+            ("1. Four", "1. Four ]#"),
+            ("Fifth.", ""),
+        ];
+
+        for &(old, new) in edits {
+            let start = document.source().find(old).unwrap();
+            document.edit(start..start + old.len(), new);
+
+            let (blocks, diagnostics) = dialect.parse_recovering(document.source());
+            let blocks: Vec<Block> = blocks.into_iter().map(Block::into_owned).collect();
+            assert_eq!(document.blocks(), blocks.as_slice(), "after replacing {:?} with {:?}", old, new);
+            assert_eq!(document.diagnostics(), diagnostics.as_slice(), "after replacing {:?} with {:?}", old, new);
+        }
+
+        let mut document = dialect.document("One.\n\nTwo.\n\nThree.\n\nFour.");
+        let start = document.source().find("Three").unwrap();
+        assert_eq!(document.edit(start..start + 5, "Third"), 1..3);
+        assert_eq!(document.edit(0..0, "Zero.\n\n"), 0..1);
+        assert_eq!(document.blocks().len(), 5);
+
+        // A block can't be reused if its line now starts somewhere else.
+        let mut document = dialect.document(" Indented.");
+        document.edit(1..1, "\n");
+        assert_eq!(document.blocks()[0].span().start, Position::new(2, 2, 1));
+    }
+
+    #[test]
+    fn dialect_parses_many_documents() {
+        let mut dialect = Dialect::new();
+        dialect.add_extension(extensions::Italics);
+        dialect.comment_syntax(CommentSyntax::line("%"));
+
+        for (i, field) in ["first /field/", "second % comment", "# third"].iter().enumerate() {
+            let blocks = dialect.parse(field).unwrap();
+            assert_eq!(blocks.len(), 1, "field {}", i);
+        }
+
+        assert_eq!(
+            dialect.emit("/one/ % two", &TextEmitter::new()).unwrap(),
+            dialect.emit("/one/", &TextEmitter::new()).unwrap()
+        );
+
+        let (blocks, diagnostics) = dialect.parse_recovering("an /unclosed italic");
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(dialect.parse("an /unclosed italic").is_ok());
+
+        dialect.deny_warnings();
+        assert!(dialect.parse("an /unclosed italic").is_err());
+        assert!(dialect.parse("a /closed/ italic").is_ok());
+    }
+
+    #[test]
+    fn dialects_are_shared_between_threads() {
+        fn send_sync<T: Send + Sync>() {}
+        send_sync::<Dialect>();
+        send_sync::<Parser>();
+        send_sync::<HtmlEmitter>();
+        send_sync::<TextEmitter>();
+        send_sync::<MarkdownEmitter>();
+        send_sync::<PublicationEmitter>();
+
+        let mut dialect = Dialect::new();
+        dialect.add_extension(extensions::Bold);
+        dialect.add_extension(extensions::Lists::new("-"));
+
+        let emitter = HtmlEmitter::new();
+
+        let sources = ["Some *bold* text", "- One\n- *Two*", "Plain"];
+        let (dialect, emitter) = (&dialect, &emitter);
+        let emitted: Vec<String> = std::thread::scope(|scope| {
+            let threads: Vec<_> = sources
+                .iter()
+                .map(|source| scope.spawn(move || dialect.emit(source, emitter).unwrap()))
+                .collect();
+            threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+        });
+
+        assert_eq!(
+            emitted,
+            vec![
+                "<p>\n  Some <strong>bold</strong> text\n</p>\n",
+                "<ul>\n  <li>\n    One\n  </li>\n  <li>\n    <strong>Two</strong>\n  </li>\n</ul>\n",
+                "<p>\n  Plain\n</p>\n",
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn extension_tags_are_serialized_by_name() {
+        use serde::de::{value::StrDeserializer, Deserialize, IntoDeserializer};
+
+        let deserializer: StrDeserializer<serde::de::value::Error> = "plugin:SERIALIZED".into_deserializer();
+        let tag = ExtensionTag::deserialize(deserializer).unwrap();
+        assert_eq!(tag, ExtensionTag::new("plugin:SERIALIZED"));
+
+        let deserializer: StrDeserializer<serde::de::value::Error> = "builtin:BOLD".into_deserializer();
+        assert_eq!(ExtensionTag::deserialize(deserializer).unwrap(), extensions::BOLD);
+    }
 }
@@ -0,0 +1,234 @@
+use crate::*;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, Read};
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Parses blocks from a reader, yielding each block as soon as the start of
+/// the next one has been read, as returned by `Dialect::read_blocks`. Only the
+/// source of the blocks that are not yet complete is kept in memory.
+///
+/// A parse error stops the reader, unless it's `recovering`. Since a block
+/// isn't known to be complete until the start of the next one has been read,
+/// an error is only reported once that has been read too, or the input ended.
+pub struct BlockReader<R> {
+    reader: R,
+    dialect: Dialect,
+    recovering: bool,
+    chunk: Box<[u8]>,
+    // Whole lines of source that have not yet been parsed into blocks.
+    buffer: String,
+    // Where in the input `buffer` starts, which is always the start of a line.
+    base: Position,
+    // Where in `buffer` parsing continues, after the last block parsed.
+    resume: Position,
+    // Bytes read past the last complete line.
+    partial: Vec<u8>,
+    // How long the buffer has to get before it's parsed again, so that a long
+    // block isn't parsed over and over while it's being read.
+    parse_at: usize,
+    blocks: VecDeque<Block<'static>>,
+    diagnostics: Vec<Diagnostic>,
+    error: Option<ReadError>,
+    at_end_of_input: bool,
+    done: bool,
+}
+
+/// Why reading blocks failed.
+#[derive(Debug)]
+pub enum ReadError {
+    Parse(Box<Diagnostic>),
+    Io(io::Error),
+}
+
+impl<R: Read> BlockReader<R> {
+    pub(crate) fn new(reader: R, dialect: Dialect) -> BlockReader<R> {
+        BlockReader {
+            reader,
+            dialect,
+            recovering: false,
+            chunk: vec![0; CHUNK_SIZE].into_boxed_slice(),
+            buffer: String::new(),
+            base: Position::default(),
+            resume: Position::default(),
+            partial: vec![],
+            parse_at: 0,
+            blocks: VecDeque::new(),
+            diagnostics: vec![],
+            error: None,
+            at_end_of_input: false,
+            done: false,
+        }
+    }
+
+    /// Like `Parser::parse_recovering`, skips blocks that fail to parse
+    /// instead of stopping. Errors and warnings are kept as `diagnostics`.
+    pub fn recovering(mut self) -> BlockReader<R> {
+        self.recovering = true;
+        self
+    }
+
+    /// The diagnostics of the blocks read so far, when `recovering`.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Reads another chunk of the input, and moves the lines it completes
+    /// into the buffer.
+    fn read_lines(&mut self) -> io::Result<()> {
+        let read = loop {
+            match self.reader.read(&mut self.chunk) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                read => break read?,
+            }
+        };
+        self.at_end_of_input = read == 0;
+
+        let len = self.partial.len();
+        self.partial.extend_from_slice(&self.chunk[..read]);
+        let complete = match self.chunk[..read].iter().rposition(|&b| b == b'\n') {
+            _ if self.at_end_of_input => self.partial.len(),
+            Some(newline) => len + newline + 1,
+            None => return Ok(()),
+        };
+        let rest = self.partial.split_off(complete);
+        let lines = String::from_utf8(std::mem::replace(&mut self.partial, rest))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.buffer.push_str(&lines);
+        Ok(())
+    }
+
+    /// Parses the blocks of the buffer that are known to be complete, and
+    /// drops their source from it.
+    fn parse_buffer(&mut self) -> Result<(), Box<Diagnostic>> {
+        let base = self.base;
+        let mut parser = self.dialect.parser(&self.buffer);
+        parser.position = self.resume;
+        parser.content_end = self.resume;
+        let mut parsed = vec![];
+        let mut parsed_up_to = self.resume;
+        let mut error = None;
+
+        parser.move_past_whitespace();
+        while !parser.is_at_end() {
+            let diagnostics = parser.diagnostics.len();
+            let block = if self.recovering {
+                Ok(parser.parse_block_recovering())
+            } else {
+                match parser.parse_block() {
+                    Ok(block) => Ok(Some(block)),
+                    Err(e) => {
+                        parser.move_to_end_of_block();
+                        Err(e)
+                    }
+                }
+            };
+            let end_of_block = parser.position();
+            parser.move_past_whitespace();
+
+            // Until the start of another block has been read, more input
+            // could still be part of this one.
+            if parser.is_at_end() && !self.at_end_of_input {
+                break;
+            }
+            match block {
+                Ok(block) => parsed.extend(block.map(Block::into_owned)),
+                Err(e) => {
+                    error = Some(Box::new(shift_diagnostic(*e, base)));
+                    break;
+                }
+            }
+            self.diagnostics.extend(parser.diagnostics.drain(diagnostics..).map(|d| shift_diagnostic(d, base)));
+            parsed_up_to = end_of_block;
+        }
+
+        // The buffer is kept from the start of the line that parsing stopped
+        // on, so that columns, and what extensions see before them, stay the
+        // same as in the whole input.
+        let progressed = parsed_up_to != self.resume;
+        let line_start = self.buffer[..parsed_up_to.offset].rfind('\n').map_or(0, |i| i + 1);
+        self.buffer.drain(..line_start);
+        self.base = shift_position(Position::new(line_start, parsed_up_to.line, 1), base);
+        self.resume = Position::new(parsed_up_to.offset - line_start, 1, parsed_up_to.column);
+        self.parse_at = if progressed { 0 } else { self.buffer.len() * 2 };
+
+        for mut block in parsed {
+            block.map_positions(&|position| shift_position(position, base));
+            self.blocks.push_back(block);
+        }
+        error.map_or(Ok(()), Err)
+    }
+}
+
+impl<R: Read> Iterator for BlockReader<R> {
+    type Item = Result<Block<'static>, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(block) = self.blocks.pop_front() {
+                return Some(Ok(block));
+            }
+            if let Some(error) = self.error.take() {
+                return Some(Err(error));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(error) = self.read_lines() {
+                self.done = true;
+                self.error = Some(ReadError::Io(error));
+                continue;
+            }
+            if self.at_end_of_input || self.buffer.len() >= self.parse_at {
+                if let Err(error) = self.parse_buffer() {
+                    self.done = true;
+                    self.error = Some(ReadError::Parse(error));
+                    continue;
+                }
+            }
+            self.done = self.at_end_of_input;
+        }
+    }
+}
+
+// Turns a position in the buffer into one in the whole input. The buffer
+// starts at the start of a line, so columns stay the same.
+fn shift_position(position: Position, base: Position) -> Position {
+    Position::new(position.offset + base.offset, position.line + base.line - 1, position.column)
+}
+
+fn shift_diagnostic(mut diagnostic: Diagnostic, base: Position) -> Diagnostic {
+    diagnostic.span = diagnostic.span.map(|position| shift_position(position, base));
+    diagnostic
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::Parse(diagnostic) => fmt::Display::fmt(diagnostic, f),
+            ReadError::Io(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReadError::Parse(diagnostic) => Some(diagnostic.as_ref()),
+            ReadError::Io(error) => Some(error),
+        }
+    }
+}
+
+impl From<Box<Diagnostic>> for ReadError {
+    fn from(diagnostic: Box<Diagnostic>) -> ReadError {
+        ReadError::Parse(diagnostic)
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(error: io::Error) -> ReadError {
+        ReadError::Io(error)
+    }
+}
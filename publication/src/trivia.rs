@@ -0,0 +1,134 @@
+use crate::*;
+use std::collections::HashMap;
+
+/// A comment, with `text` as written in the source, markers included. The
+/// lines of a block comment after the first are indented relative to the
+/// column that the comment starts at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+/// How comments are written. By default, `#` starts a comment that runs to
+/// the end of the line, and there are no block comments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommentSyntax {
+    pub line: Option<String>,
+    pub block: Option<(String, String)>,
+}
+
+impl CommentSyntax {
+    /// No comments at all, so that `#` is always text.
+    pub fn none() -> CommentSyntax {
+        CommentSyntax {
+            line: None,
+            block: None,
+        }
+    }
+
+    /// Comments from `marker` to the end of the line.
+    pub fn line<M: Into<String>>(marker: M) -> CommentSyntax {
+        CommentSyntax {
+            line: Some(marker.into()),
+            block: None,
+        }
+    }
+
+    /// Comments from `open` to `close`, which may span several lines.
+    pub fn block<O: Into<String>, C: Into<String>>(open: O, close: C) -> CommentSyntax {
+        CommentSyntax::none().with_block(open, close)
+    }
+
+    pub fn with_block<O: Into<String>, C: Into<String>>(mut self, open: O, close: C) -> CommentSyntax {
+        self.block = Some((open.into(), close.into()));
+        self
+    }
+
+    /// Whether `text` starts with a comment.
+    pub fn starts_comment(&self, text: &str) -> bool {
+        let starts_with = |marker: &String| !marker.is_empty() && text.starts_with(marker.as_str());
+        self.line.as_ref().map(starts_with).unwrap_or(false)
+            || self.block.as_ref().map(|(open, _)| starts_with(open)).unwrap_or(false)
+    }
+}
+
+impl Default for CommentSyntax {
+    fn default() -> CommentSyntax {
+        CommentSyntax::line("#")
+    }
+}
+
+/// The comments around a paragraph or other block of elements.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BlockTrivia {
+    /// Comments on the lines before the block.
+    pub leading: Vec<Comment>,
+    /// Comments between the lines of the block.
+    pub inline: Vec<Comment>,
+    /// A comment at the end of the last line of the block.
+    pub trailing: Vec<Comment>,
+}
+
+/// The comments of a document, as returned by `Parser::parse_lossless`. They
+/// are kept beside the blocks rather than in them, so that emitters that
+/// have no use for comments don't need to know about them.
+///
+/// Comments are attached to the innermost blocks of elements, so that a
+/// comment before a list ends up leading its first paragraph.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Trivia {
+    blocks: HashMap<Span, BlockTrivia>,
+    /// Comments after the last block.
+    pub end: Vec<Comment>,
+}
+
+impl Trivia {
+    pub fn attach(blocks: &[Block], comments: Vec<Comment>) -> Trivia {
+        let mut spans = vec![];
+        leaf_spans(blocks, &mut spans);
+
+        let mut trivia = Trivia::default();
+        let mut next = 0;
+        for comment in comments {
+            let start = comment.span.start;
+            while next < spans.len() && spans[next].end.offset <= start.offset {
+                next += 1;
+            }
+            let previous = next.checked_sub(1).map(|i| spans[i]);
+            match (previous, spans.get(next)) {
+                (Some(previous), _) if previous.end.line == start.line => {
+                    trivia.blocks.entry(previous).or_default().trailing.push(comment)
+                }
+                (_, Some(span)) if start.offset < span.start.offset => {
+                    trivia.blocks.entry(*span).or_default().leading.push(comment)
+                }
+                (_, Some(span)) => trivia.blocks.entry(*span).or_default().inline.push(comment),
+                (_, None) => trivia.end.push(comment),
+            }
+        }
+        trivia
+    }
+
+    /// The comments attached to `block`, if any. Only blocks of elements have
+    /// comments attached to them.
+    pub fn of(&self, block: &Block) -> Option<&BlockTrivia> {
+        match block {
+            Block::ExtensionBlocks(..) => None,
+            block => self.blocks.get(&block.span()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() && self.end.is_empty()
+    }
+}
+
+fn leaf_spans(blocks: &[Block], spans: &mut Vec<Span>) {
+    for block in blocks {
+        match block {
+            Block::ExtensionBlocks(_, blocks, _, _) => leaf_spans(blocks, spans),
+            block => spans.push(block.span()),
+        }
+    }
+}